@@ -69,6 +69,12 @@ public class Circle {
     public String toString() {        
         return  "sphere" + " " + x + " " + y + " " + z + " " + radius + " " + color.getRed() + " " + color.getGreen() + " " + color.getBlue() ;
     }
+
+    public String toJson() {
+        return "{ \"type\": \"sphere\", \"center\": [" + x + ", " + y + ", " + z + "], \"radius\": " + radius
+                + ", \"material\": { \"color\": [" + color.getRed() / 255.0 + ", " + color.getGreen() / 255.0 + ", " + color.getBlue() / 255.0 + "]"
+                + ", \"surface\": { \"type\": \"reflective\", \"reflectivity\": 0.1 } } }";
+    }
     
     
     
@@ -66,4 +66,9 @@ public class Light {
         return "light" + " " + x + " " + y + " " + z + " "+ intensity +  " " + color.getRed() + " " + color.getGreen() + " " + color.getBlue();
     }
 
+    public String toJson() {
+        return "{ \"type\": \"directional\", \"direction\": [" + x + ", " + y + ", " + z + "], \"intensity\": " + intensity
+                + ", \"color\": [" + color.getRed() / 255.0 + ", " + color.getGreen() / 255.0 + ", " + color.getBlue() / 255.0 + "] }";
+    }
+
 }
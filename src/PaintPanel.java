@@ -1,5 +1,6 @@
 import java.awt.Color;
 import java.awt.Graphics;
+import java.awt.image.BufferedImage;
 import java.io.BufferedWriter;
 import java.io.File;
 import java.io.FileWriter;
@@ -16,6 +17,7 @@ public class PaintPanel extends JPanel {
 
     private ArrayList<Circle> circleList;
     private ArrayList<Light> lightList;
+    private BufferedImage image;
 
     public PaintPanel() {
         circleList = new ArrayList<>();
@@ -31,10 +33,20 @@ public class PaintPanel extends JPanel {
         lightList.add(l);
     }
 
+    public void setImage(BufferedImage image) {
+        this.image = image;
+        repaint();
+    }
+
     @Override
     protected void paintComponent(Graphics g) {
         super.paintComponent(g);
 
+        if (image != null) {
+            g.drawImage(image, 0, 0, this.getWidth(), this.getHeight(), null);
+            return;
+        }
+
         for (int i = 0; i < circleList.size(); i++) {
             g.setColor(circleList.get(i).getColor());
             g.fillOval((int) (this.getWidth() / 2 + circleList.get(i).getX() * 50 - ((circleList.get(i).getRadius() * 50) / 2)),(int) (this.getHeight() / 2 - circleList.get(i).getY() * 50 + (circleList.get(i).getRadius() * 50) / 2),(int) ((circleList.get(i).getRadius()+0.01) * 100),(int) ((circleList.get(i).getRadius()+0.01) * 100));
@@ -42,20 +54,32 @@ public class PaintPanel extends JPanel {
     }
 
     public void saveToFile() throws IOException {
-        File inf = new File("inf.txt");
-        BufferedWriter writer = new BufferedWriter(new FileWriter(inf));
+        File scene = new File("scene.json");
+        BufferedWriter writer = new BufferedWriter(new FileWriter(scene));
+        writer.write(toJson());
+        writer.close();
+    }
+
+    public String toJson() {
+        StringBuilder json = new StringBuilder();
+        json.append("{\n    \"width\": 1920,\n    \"height\": 1080,\n    \"bias\": 0.1,\n    \"max_rec\": 10,\n");
+
+        json.append("    \"elements\": [\n");
         for (int i = 0; i < circleList.size(); i++) {
-            writer.write(circleList.get(i).toString()+"\n");
+            json.append("        " + circleList.get(i).toJson() + (i + 1 < circleList.size() ? ",\n" : "\n"));
         }
+        json.append("    ],\n");
 
         if(lightList.size() == 0){
             System.out.println("No lights have been added!");
         }
+        json.append("    \"lights\": [\n");
         for (int i = 0; i < lightList.size(); i++) {
-            writer.write(lightList.get(i).toString() + "\n");
+            json.append("        " + lightList.get(i).toJson() + (i + 1 < lightList.size() ? ",\n" : "\n"));
         }
+        json.append("    ]\n}\n");
 
-        writer.close();
+        return json.toString();
     }
 
 }
@@ -0,0 +1,17 @@
+/**
+ * Gets told how far a render has come, called from the rendering thread after every finished tile
+ */
+public interface RenderListener {
+
+    /**
+     * @param fraction between 0 and 1
+     * @param elapsed seconds since the render started
+     * @param eta estimated seconds left, negative while unknown
+     */
+    void progress(double fraction, double elapsed, double eta);
+
+    /**
+     * Checked after every progress call, returning true stops the render
+     */
+    boolean isCancelled();
+}
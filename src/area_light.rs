@@ -0,0 +1,190 @@
+use std::f64::consts::PI;
+use crate::point::Point;
+use crate::sampling::{pixel_offsets, Rng, Sampling};
+use crate::vector::Vector3;
+use crate::{Color, Element, LightSample};
+
+pub enum AreaShape {
+    // Centered on center, spanning half of u and v to every side
+    Rectangle { center: Point, u: Vector3, v: Vector3 },
+    Disk { center: Point, normal: Vector3, radius: f64 },
+    Sphere { center: Point, radius: f64 },
+}
+
+// Light with a size, shadow rays go to `samples` points spread over the shape so shadows get soft edges
+pub struct AreaLight {
+    pub shape: AreaShape,
+    pub color: Color,
+    pub intensity: f32,
+    pub samples: u32,
+}
+
+impl AreaShape {
+    pub fn center(&self) -> Point {
+        match *self {
+            AreaShape::Rectangle { center, .. } => center,
+            AreaShape::Disk { center, .. } => center,
+            AreaShape::Sphere { center, .. } => center,
+        }
+    }
+
+    // Maps (s, t) in [0, 1) to a point on the shape, and the normal there if the shape is flat
+    fn point_at(&self, s: f64, t: f64, hit_point: &Point) -> (Point, Option<Vector3>) {
+        match *self {
+            AreaShape::Rectangle { center, u, v } => (center + u * (s - 0.5) + v * (t - 0.5), Some(u.cross(&v).normalize())),
+            AreaShape::Disk { center, normal, radius } => {
+                let normal = normal.normalize();
+                let (tangent, bitangent) = normal.orthonormal_basis();
+                let (r, phi) = (radius * s.sqrt(), 2.0 * PI * t);
+                (center + tangent * (r * phi.cos()) + bitangent * (r * phi.sin()), Some(normal))
+            }
+            AreaShape::Sphere { center, radius } => (center + sphere_offset(s, t, &(*hit_point - center)) * radius, None),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            AreaShape::Rectangle { center, u, v } => center.is_finite() && u.is_finite() && v.is_finite() && u.cross(&v).length() > 0.0,
+            AreaShape::Disk { center, normal, radius } => center.is_finite() && normal.is_finite() && normal.length() > 0.0 && radius > 0.0 && radius.is_finite(),
+            AreaShape::Sphere { center, radius } => center.is_finite() && radius > 0.0 && radius.is_finite(),
+        }
+    }
+}
+
+// Unit vector for (s, t) in [0, 1), always on the half of the sphere facing `towards`
+fn sphere_offset(s: f64, t: f64, towards: &Vector3) -> Vector3 {
+    let z = 1.0 - 2.0 * s;
+    let ring = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * t;
+    let offset = Vector3 { x: ring * phi.cos(), y: ring * phi.sin(), z };
+    // The far side is hidden behind the sphere itself, mirror it to the near side
+    let towards = towards.normalize();
+    if offset.dot(&towards) < 0.0 {
+        offset - towards * (2.0 * offset.dot(&towards))
+    } else {
+        offset
+    }
+}
+
+impl AreaLight {
+    // Every sample is treated as a point light with an equal share of the intensity,
+    // flat shapes shine from both faces and get dimmer when seen edge on
+    pub fn sample(&self, hit_point: &Point, rng: &mut Rng) -> Vec<LightSample> {
+        pixel_offsets(Sampling::Jittered, self.samples, rng)
+            .into_iter()
+            .map(|(s, t)| {
+                let (pos, normal) = self.shape.point_at(s, t, hit_point);
+                let to_light = pos - *hit_point;
+                let direction = to_light.normalize();
+                let facing = normal.map_or(1.0, |n| n.dot(&direction).abs() as f32);
+                LightSample {
+                    direction,
+                    distance: to_light.length(),
+                    intensity: facing * self.intensity / (4.0 * ::std::f32::consts::PI * to_light.norm() as f32),
+                }
+            })
+            .collect()
+    }
+}
+
+// Spheres and triangles, the other shapes can glow but don't light anything up
+pub fn can_sample(element: &Element) -> bool {
+    matches!(*element, Element::Sphere(_) | Element::Triangle(_))
+}
+
+// Shadow rays towards an element with an emissive material, the intensity is scaled by the area so
+// the light it gives off matches how bright it looks
+pub fn sample_emitter(element: &Element, samples: u32, hit_point: &Point, rng: &mut Rng) -> Vec<LightSample> {
+    let strength = element.material().emission_strength;
+
+    pixel_offsets(Sampling::Jittered, samples, rng)
+        .into_iter()
+        .filter_map(|(s, t)| {
+            let (pos, normal, area, two_sided) = match *element {
+                Element::Sphere(ref sphere) => {
+                    let offset = sphere_offset(s, t, &(*hit_point - sphere.center));
+                    (sphere.center + offset * sphere.radius, offset, 2.0 * PI * sphere.radius * sphere.radius, false)
+                }
+                Element::Triangle(ref triangle) => {
+                    let (pos, normal, area) = triangle.sample_point(s, t);
+                    (pos, normal, area, true)
+                }
+                _ => return None,
+            };
+
+            let to_light = pos - *hit_point;
+            let direction = to_light.normalize();
+            let cos_light = -normal.dot(&direction);
+            let facing = if two_sided { cos_light.abs() } else { cos_light.max(0.0) };
+            Some(LightSample {
+                direction,
+                distance: to_light.length(),
+                intensity: strength * (facing * area / to_light.norm()) as f32,
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn test_area_light_samples(){
+    let light = AreaLight {
+        shape: AreaShape::Rectangle {
+            center: Point { x: 0.0, y: 2.0, z: 0.0 },
+            u: Vector3 { x: 2.0, y: 0.0, z: 0.0 },
+            v: Vector3 { x: 0.0, y: 0.0, z: 2.0 },
+        },
+        color: Color { red: 1.0, green: 1.0, blue: 1.0 },
+        intensity: 100.0,
+        samples: 16,
+    };
+
+    let mut rng = Rng::new(1);
+    let samples = light.sample(&Point::zero(), &mut rng);
+    assert_eq!(samples.len(), 16);
+    for sample in &samples {
+        let pos = Point::zero() + sample.direction * sample.distance;
+        assert!((pos.y - 2.0).abs() < 1e-9);
+        assert!(pos.x.abs() <= 1.0 && pos.z.abs() <= 1.0);
+        assert!(sample.intensity > 0.0);
+    }
+
+    let sphere = AreaShape::Sphere { center: Point { x: 0.0, y: 2.0, z: 0.0 }, radius: 0.5 };
+    for i in 0..16 {
+        let (pos, _) = sphere.point_at(i as f64 / 16.0, 0.3, &Point::zero());
+        assert!((pos - sphere.center()).length() - 0.5 < 1e-9);
+        assert!(pos.y <= 2.0 + 1e-9);
+    }
+
+    let disk = AreaShape::Disk { center: Point::zero(), normal: Vector3 { x: 0.0, y: 1.0, z: 0.0 }, radius: 0.0 };
+    assert!(!disk.is_valid());
+}
+
+#[test]
+fn test_soft_shadow(){
+    use std::path::Path;
+
+    // A wall ending at x = 0 halfway between the floor and a 2 wide light
+    let text = r#"{ "width": 10, "height": 10,
+        "elements": [
+            { "type": "plane", "center": [0, 0, 0], "normal": [0, -1, 0], "material": { "color": [1, 1, 1] } },
+            { "type": "box", "min": [-5, 1.9, -5], "max": [0, 2.1, 5], "material": { "color": [1, 1, 1] } }
+        ],
+        "lights": [{ "type": "rectangle", "center": [0, 4, 0], "u": [2, 0, 0], "v": [0, 0, 2],
+                     "color": [1, 1, 1], "intensity": 100, "samples": 64 }] }"#;
+    let walled = crate::parse_scene(text, "test.json", Path::new(".")).unwrap();
+    let open = crate::parse_scene(&text.replace("[-5, 1.9, -5]", "[-5, 1.9, 50]").replace("[0, 2.1, 5]", "[0, 2.1, 60]"),
+        "test.json", Path::new(".")).unwrap();
+    let up = Vector3 { x: 0.0, y: 1.0, z: 0.0 };
+    // Light reaching the floor at x with the wall over the fraction without it, same samples both times
+    let fraction = |x: f64| {
+        let point = Point { x, y: 0.0, z: 0.0 };
+        let light = |scene: &crate::Scene| crate::direct_light(scene, &scene.elements()[0], point, up, &mut Rng::for_point(&point)).red;
+        light(&walled) / light(&open)
+    };
+
+    assert_eq!(fraction(-3.0), 0.0);
+    assert_eq!(fraction(3.0), 1.0);
+    // Straight below the edge the wall hides half of the light
+    let penumbra = fraction(0.0);
+    assert!(penumbra > 0.3 && penumbra < 0.7, "penumbra got {} of the light", penumbra);
+}
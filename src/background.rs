@@ -0,0 +1,117 @@
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use image::codecs::hdr::HdrDecoder;
+use crate::error::RenderError;
+use crate::vector::Vector3;
+use crate::{Color, ColorSpace, LinearImage};
+
+// What rays see when they miss everything
+pub enum Background {
+    Color(Color),
+    // Blends from bottom when looking straight down to top when looking straight up
+    Gradient { bottom: Color, top: Color },
+    Image(EnvironmentMap),
+}
+
+impl Background {
+    pub fn black() -> Background {
+        Background::Color(Color::black())
+    }
+
+    pub fn sample(&self, direction: &Vector3) -> Color {
+        match *self {
+            Background::Color(ref c) => c.clone(),
+            Background::Gradient { ref bottom, ref top } => {
+                let t = (direction.normalize().y as f32 + 1.0) / 2.0;
+                bottom.clone() * (1.0 - t) + top.clone() * t
+            }
+            Background::Image(ref map) => map.sample(direction),
+        }
+    }
+}
+
+// Equirectangular panorama, the middle of the image is straight down -Z
+pub struct EnvironmentMap {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color>,
+}
+
+impl EnvironmentMap {
+    // .hdr files keep their full range, anything else is taken to be sRGB
+    pub fn load(path: &Path) -> Result<EnvironmentMap, RenderError> {
+        let missing = |message: String| RenderError::MissingTexture {
+            path: path.display().to_string(),
+            message,
+        };
+
+        let is_hdr = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("hdr"));
+        if is_hdr {
+            let file = File::open(path).map_err(|error| RenderError::Io { path: path.display().to_string(), error })?;
+            let decoder = HdrDecoder::new(BufReader::new(file)).map_err(|e| missing(e.to_string()))?;
+            let meta = decoder.metadata();
+            let pixels = decoder.read_image_hdr().map_err(|e| missing(e.to_string()))?;
+            Ok(EnvironmentMap {
+                width: meta.width,
+                height: meta.height,
+                pixels: pixels.iter().map(|p| Color { red: p[0], green: p[1], blue: p[2] }).collect(),
+            })
+        } else {
+            let img = image::open(path).map_err(|e| missing(e.to_string()))?;
+            let linear = LinearImage::from_image(&img, ColorSpace::Srgb);
+            Ok(EnvironmentMap {
+                width: linear.width,
+                height: linear.height,
+                pixels: linear.pixels,
+            })
+        }
+    }
+
+    pub fn sample(&self, direction: &Vector3) -> Color {
+        let d = direction.normalize();
+        let u = 0.5 + d.x.atan2(-d.z) / (2.0 * PI);
+        let v = d.y.clamp(-1.0, 1.0).acos() / PI;
+
+        let x = ((u * self.width as f64) as u32).min(self.width - 1);
+        let y = ((v * self.height as f64) as u32).min(self.height - 1);
+        self.pixels[(y * self.width + x) as usize].clone()
+    }
+}
+
+#[test]
+fn test_background_sample(){
+    let gradient = Background::Gradient {
+        bottom: Color::black(),
+        top: Color { red: 1.0, green: 1.0, blue: 1.0 },
+    };
+    assert_eq!(gradient.sample(&Vector3 { x: 0.0, y: 1.0, z: 0.0 }).red, 1.0);
+    assert_eq!(gradient.sample(&Vector3 { x: 0.0, y: -2.0, z: 0.0 }).red, 0.0);
+    assert_eq!(gradient.sample(&Vector3 { x: 1.0, y: 0.0, z: 0.0 }).red, 0.5);
+
+    // 4x2 map, every pixel a different red
+    let map = EnvironmentMap {
+        width: 4,
+        height: 2,
+        pixels: (0..8).map(|i| Color { red: i as f32, green: 0.0, blue: 0.0 }).collect(),
+    };
+    let red = |x: f64, y: f64, z: f64| map.sample(&Vector3 { x, y, z }).red;
+    assert_eq!(red(0.0, 0.1, -1.0), 2.0);
+    assert_eq!(red(1.0, 0.1, 0.0), 3.0);
+    assert_eq!(red(-1.0, 0.1, 0.0), 1.0);
+    // The seam is straight behind, the edge columns are on either side of it
+    assert_eq!(red(0.1, -0.1, 1.0), 7.0);
+    assert_eq!(red(-0.1, -0.1, 1.0), 4.0);
+
+    assert!(EnvironmentMap::load(Path::new("no/such/sky.hdr")).is_err());
+
+    // Values above 1 have to survive loading an .hdr
+    let path = std::env::temp_dir().join("embed_test_sky.hdr");
+    let sky = vec![image::Rgb([4.0f32, 0.5, 0.25]); 8];
+    image::codecs::hdr::HdrEncoder::new(File::create(&path).unwrap()).encode(&sky, 4, 2).unwrap();
+    let loaded = EnvironmentMap::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!((loaded.width, loaded.height), (4, 2));
+    assert!((loaded.sample(&Vector3 { x: 0.0, y: 1.0, z: 0.0 }).red - 4.0).abs() < 0.1);
+}
@@ -0,0 +1,158 @@
+// Headless renderer, renders a scene file straight to an image without the JVM
+use std::env;
+use std::path::Path;
+use std::process;
+use std::time::Instant;
+use embed::{load_scene, render_hdr, OutputFormat, ToneMap};
+
+const USAGE: &str = "usage: render <scene.json> <output.png> [--width N] [--height N] [--samples N] [--max-rec N] [--threads N] [--tone-map clamp|reinhard|aces] [--exposure STOPS] [--format image|exr|hdr]";
+
+struct Options {
+    scene: String,
+    output: String,
+    width: Option<u32>,
+    height: Option<u32>,
+    samples: Option<u32>,
+    max_rec: Option<u32>,
+    threads: Option<usize>,
+    tone_map: Option<ToneMap>,
+    exposure: Option<f32>,
+    format: Option<OutputFormat>,
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} needs a value", flag))?;
+    value.parse().map_err(|_| format!("invalid value '{}' for {}", value, flag))
+}
+
+fn parse_tone_map(value: Option<String>) -> Result<ToneMap, String> {
+    match value.as_deref() {
+        Some("clamp") => Ok(ToneMap::Clamp),
+        Some("reinhard") => Ok(ToneMap::Reinhard),
+        Some("aces") => Ok(ToneMap::Aces),
+        Some(other) => Err(format!("invalid value '{}' for --tone-map", other)),
+        None => Err("--tone-map needs a value".to_string()),
+    }
+}
+
+fn parse_format(value: Option<String>) -> Result<OutputFormat, String> {
+    match value.as_deref() {
+        Some("image") => Ok(OutputFormat::Image),
+        Some("exr") => Ok(OutputFormat::Exr),
+        Some("hdr") => Ok(OutputFormat::Hdr),
+        Some(other) => Err(format!("invalid value '{}' for --format", other)),
+        None => Err("--format needs a value".to_string()),
+    }
+}
+
+// None when only the usage was asked for
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Options>, String> {
+    let mut positional = Vec::new();
+    let mut options = Options {
+        scene: String::new(),
+        output: String::new(),
+        width: None,
+        height: None,
+        samples: None,
+        max_rec: None,
+        threads: None,
+        tone_map: None,
+        exposure: None,
+        format: None,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--width" => options.width = Some(parse_value(&arg, args.next())?),
+            "--height" => options.height = Some(parse_value(&arg, args.next())?),
+            "--samples" => options.samples = Some(parse_value(&arg, args.next())?),
+            "--max-rec" => options.max_rec = Some(parse_value(&arg, args.next())?),
+            "--threads" => options.threads = Some(parse_value(&arg, args.next())?),
+            "--tone-map" => options.tone_map = Some(parse_tone_map(args.next())?),
+            "--exposure" => options.exposure = Some(parse_value(&arg, args.next())?),
+            "--format" => options.format = Some(parse_format(args.next())?),
+            "-h" | "--help" => return Ok(None),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => positional.push(arg),
+        }
+    }
+
+    if positional.len() != 2 {
+        return Err(USAGE.to_string());
+    }
+    options.output = positional.pop().unwrap();
+    options.scene = positional.pop().unwrap();
+
+    Ok(Some(options))
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let mut scene = load_scene(Path::new(&options.scene)).map_err(|e| e.to_string())?;
+
+    if let Some(width) = options.width {
+        scene.width = width;
+    }
+    if let Some(height) = options.height {
+        scene.height = height;
+    }
+    if let Some(samples) = options.samples {
+        scene.samples = samples;
+    }
+    if let Some(max_rec) = options.max_rec {
+        scene.max_rec = max_rec;
+    }
+    if let Some(threads) = options.threads {
+        scene.threads = threads;
+    }
+    if let Some(tone_map) = options.tone_map {
+        scene.tone_map = tone_map;
+    }
+    if let Some(exposure) = options.exposure {
+        scene.exposure = exposure;
+    }
+
+    let start = Instant::now();
+    let framebuffer = render_hdr(&scene).map_err(|e| e.to_string())?;
+    println!("Rendered {}x{} in {:?}", scene.width, scene.height, start.elapsed());
+
+    // The extension decides unless --format says otherwise
+    let output = Path::new(&options.output);
+    let format = options.format.unwrap_or_else(|| OutputFormat::from_path(output));
+    framebuffer.save(output, format, scene.tone_map, scene.exposure)
+        .map_err(|e| format!("could not save {}: {}", options.output, e))
+}
+
+fn main() {
+    let result = parse_args(env::args().skip(1)).and_then(|options| match options {
+        Some(options) => run(options),
+        None => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+    });
+
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
+
+#[test]
+fn test_parse_args(){
+    let parse = |args: &[&str]| parse_args(args.iter().map(|a| a.to_string()));
+
+    let options = parse(&["scene.json", "out.exr", "--samples", "4", "--tone-map", "aces"]).unwrap().unwrap();
+    assert_eq!((options.scene.as_str(), options.output.as_str()), ("scene.json", "out.exr"));
+    assert_eq!(options.samples, Some(4));
+    assert_eq!(options.tone_map, Some(ToneMap::Aces));
+
+    assert!(parse(&["--help"]).unwrap().is_none());
+    assert!(parse(&["scene.json", "out.png", "-h"]).unwrap().is_none());
+
+    assert_eq!(parse(&["scene.json", "out.png", "--width"]).err().unwrap(), "--width needs a value");
+    assert_eq!(parse(&["scene.json", "out.png", "--width", "wide"]).err().unwrap(), "invalid value 'wide' for --width");
+    assert_eq!(parse(&["scene.json", "out.png", "--tone-map"]).err().unwrap(), "--tone-map needs a value");
+    assert_eq!(parse(&["scene.json", "out.png", "--format", "gif"]).err().unwrap(), "invalid value 'gif' for --format");
+    assert_eq!(parse(&["scene.json", "out.png", "--fast"]).err().unwrap(), "unknown option --fast");
+    assert_eq!(parse(&["scene.json"]).err().unwrap(), USAGE);
+}
@@ -0,0 +1,317 @@
+use std::time::{Duration, Instant};
+use crate::point::Point;
+use crate::vector::Vector3;
+use crate::{Element, Intersectable, Ray};
+
+const MAX_LEAF_SIZE: usize = 2;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Aabb {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Aabb {
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Point::from_one(f64::INFINITY),
+            max: Point::from_one(f64::NEG_INFINITY),
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point {
+                x: self.min.x.min(other.min.x),
+                y: self.min.y.min(other.min.y),
+                z: self.min.z.min(other.min.z),
+            },
+            max: Point {
+                x: self.max.x.max(other.max.x),
+                y: self.max.y.max(other.max.y),
+                z: self.max.z.max(other.max.z),
+            },
+        }
+    }
+
+    // Can end up with min above max when they don't overlap
+    pub fn intersection(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point {
+                x: self.min.x.max(other.min.x),
+                y: self.min.y.max(other.min.y),
+                z: self.min.z.max(other.min.z),
+            },
+            max: Point {
+                x: self.max.x.min(other.max.x),
+                y: self.max.y.min(other.max.y),
+                z: self.max.z.min(other.max.z),
+            },
+        }
+    }
+
+    pub fn center(&self) -> Point {
+        self.min + (self.max - self.min) * 0.5
+    }
+
+    // 0, 1 or 2 for x, y or z
+    pub fn longest_axis(&self) -> usize {
+        let size = self.max - self.min;
+        if size.x >= size.y && size.x >= size.z {
+            0
+        } else if size.y >= size.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    // Slab test, true if the ray enters the box closer than max_distance
+    pub fn hit(&self, ray: &Ray, inv_direction: &Vector3, max_distance: f64) -> bool {
+        let mut t_min = 0.0f64;
+        let mut t_max = max_distance;
+
+        let axes = [
+            (self.min.x, self.max.x, ray.origin.x, inv_direction.x),
+            (self.min.y, self.max.y, ray.origin.y, inv_direction.y),
+            (self.min.z, self.max.z, ray.origin.z, inv_direction.z),
+        ];
+
+        for &(min, max, origin, inv) in axes.iter() {
+            let t0 = (min - origin) * inv;
+            let t1 = (max - origin) * inv;
+            let (near, far) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+
+            t_min = t_min.max(near);
+            t_max = t_max.min(far);
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+
+    // The part of from..to along the ray that is inside the box
+    pub fn span(&self, ray: &Ray, from: f64, to: f64) -> Option<(f64, f64)> {
+        let (mut t_min, mut t_max) = (from, to);
+
+        let axes = [
+            (self.min.x, self.max.x, ray.origin.x, ray.direction.x),
+            (self.min.y, self.max.y, ray.origin.y, ray.direction.y),
+            (self.min.z, self.max.z, ray.origin.z, ray.direction.z),
+        ];
+
+        for &(min, max, origin, direction) in axes.iter() {
+            let t0 = (min - origin) / direction;
+            let t1 = (max - origin) / direction;
+            let (near, far) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+
+            t_min = t_min.max(near);
+            t_max = t_max.min(far);
+        }
+        if t_min <= t_max { Some((t_min, t_max)) } else { None }
+    }
+}
+
+fn axis(point: &Point, axis: usize) -> f64 {
+    match axis {
+        0 => point.x,
+        1 => point.y,
+        _ => point.z,
+    }
+}
+
+enum BvhNode {
+    Leaf { bounds: Aabb, start: usize, count: usize },
+    Interior { bounds: Aabb, left: usize, right: usize },
+}
+
+impl BvhNode {
+    fn bounds(&self) -> &Aabb {
+        match *self {
+            BvhNode::Leaf { ref bounds, .. } => bounds,
+            BvhNode::Interior { ref bounds, .. } => bounds,
+        }
+    }
+}
+
+// Bounding volume hierarchy over the elements of a scene. Elements without a
+// bounding box (planes) are kept in a separate list and always tested.
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+    unbounded: Vec<usize>,
+
+    pub build_time: Duration,
+}
+
+impl Bvh {
+    pub fn build(elements: &[Element]) -> Bvh {
+        let start = Instant::now();
+
+        let mut bounded: Vec<(usize, Aabb)> = Vec::new();
+        let mut unbounded = Vec::new();
+
+        for (i, element) in elements.iter().enumerate() {
+            match element.bounding_box() {
+                Some(bounds) => bounded.push((i, bounds)),
+                None => unbounded.push(i),
+            }
+        }
+
+        let mut bvh = Bvh {
+            nodes: Vec::new(),
+            indices: Vec::with_capacity(bounded.len()),
+            unbounded,
+            build_time: Duration::default(),
+        };
+
+        if !bounded.is_empty() {
+            bvh.build_node(&mut bounded);
+        }
+
+        bvh.build_time = start.elapsed();
+        bvh
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    // Returns the index of the node that was built
+    fn build_node(&mut self, items: &mut [(usize, Aabb)]) -> usize {
+        let bounds = items.iter().fold(Aabb::empty(), |b, item| b.union(&item.1));
+
+        if items.len() <= MAX_LEAF_SIZE {
+            let start = self.indices.len();
+            self.indices.extend(items.iter().map(|item| item.0));
+            self.nodes.push(BvhNode::Leaf { bounds, start, count: items.len() });
+            return self.nodes.len() - 1;
+        }
+
+        let centers = items.iter().fold(Aabb::empty(), |b, item| {
+            let c = item.1.center();
+            b.union(&Aabb { min: c, max: c })
+        });
+        let split_axis = centers.longest_axis();
+
+        items.sort_by(|a, b| {
+            axis(&a.1.center(), split_axis)
+                .partial_cmp(&axis(&b.1.center(), split_axis))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        // Reserve the slot so the children end up after their parent
+        let node = self.nodes.len();
+        self.nodes.push(BvhNode::Leaf { bounds, start: 0, count: 0 });
+
+        let (left_items, right_items) = items.split_at_mut(items.len() / 2);
+        let left = self.build_node(left_items);
+        let right = self.build_node(right_items);
+
+        self.nodes[node] = BvhNode::Interior { bounds, left, right };
+        node
+    }
+
+    // Closest hit as (distance, index into elements)
+    pub fn trace(&self, ray: &Ray, elements: &[Element]) -> Option<(f64, usize)> {
+        let mut closest: Option<(f64, usize)> = None;
+
+        for &i in &self.unbounded {
+            closest = closer(closest, elements[i].intersect(ray), i);
+        }
+
+        if self.nodes.is_empty() {
+            return closest;
+        }
+
+        let inv_direction = Vector3 {
+            x: ray.direction.x.recip(),
+            y: ray.direction.y.recip(),
+            z: ray.direction.z.recip(),
+        };
+
+        let mut stack = vec![0];
+
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            let max_distance = closest.map(|c| c.0).unwrap_or(f64::INFINITY);
+
+            if !node.bounds().hit(ray, &inv_direction, max_distance) {
+                continue;
+            }
+
+            match *node {
+                BvhNode::Leaf { start, count, .. } => {
+                    for &i in &self.indices[start..start + count] {
+                        closest = closer(closest, elements[i].intersect(ray), i);
+                    }
+                }
+                BvhNode::Interior { left, right, .. } => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+            }
+        }
+
+        closest
+    }
+}
+
+fn closer(closest: Option<(f64, usize)>, distance: Option<f64>, index: usize) -> Option<(f64, usize)> {
+    match (closest, distance) {
+        (Some(c), Some(d)) if d < c.0 => Some((d, index)),
+        (None, Some(d)) if d.is_finite() => Some((d, index)),
+        _ => closest,
+    }
+}
+
+#[test]
+fn test_bvh_matches_linear_trace(){
+    use crate::{Color, Material, Plane, Sphere};
+
+    let material = || Material::diffuse(Color { red: 1.0, green: 1.0, blue: 1.0 });
+
+    let mut seed = 12345u64;
+    let mut random = move || {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        ((seed >> 33) as f64) / ((1u64 << 31) as f64)
+    };
+
+    let mut elements = vec![Element::Plane(Plane {
+        center: Point { x: 0.0, y: -10.0, z: 0.0 },
+        normal: Vector3 { x: 0.0, y: -1.0, z: 0.0 },
+        material: material(),
+    })];
+    for _ in 0..200 {
+        elements.push(Element::Sphere(Sphere {
+            center: Point {
+                x: random() * 20.0 - 10.0,
+                y: random() * 20.0 - 10.0,
+                z: random() * -20.0 - 2.0,
+            },
+            radius: random() * 0.8 + 0.1,
+            material: material(),
+        }));
+    }
+
+    let bvh = Bvh::build(&elements);
+    assert!(bvh.node_count() > 1);
+
+    for _ in 0..1000 {
+        let ray = Ray {
+            origin: Point::zero(),
+            direction: Vector3 {
+                x: random() * 2.0 - 1.0,
+                y: random() * 2.0 - 1.0,
+                z: -1.0,
+            }.normalize(),
+        };
+
+        let linear = elements.iter()
+            .filter_map(|e| e.intersect(&ray))
+            .fold(None, |c: Option<f64>, d| Some(c.map_or(d, |c| c.min(d))));
+
+        assert_eq!(linear, bvh.trace(&ray, &elements).map(|c| c.0));
+    }
+}
@@ -0,0 +1,102 @@
+use crate::point::Point;
+use crate::vector::Vector3;
+
+// Which side of the image the fov spans, the other side follows the aspect ratio
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FovAxis {
+    Horizontal,
+    Vertical,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Camera {
+    pub eye: Point,
+    pub target: Point,
+    pub up: Vector3,
+    pub fov: f64,  // in degrees
+    pub fov_axis: FovAxis,
+}
+
+impl Camera {
+    pub fn new(eye: Point, target: Point, up: Vector3, fov: f64) -> Camera {
+        Camera {
+            eye,
+            target,
+            up,
+            fov,
+            fov_axis: FovAxis::Vertical,
+        }
+    }
+
+    // Sits in the origin and looks down -Z, same as the old fixed camera
+    pub fn origin(fov: f64) -> Camera {
+        Camera::new(
+            Point::zero(),
+            Point { x: 0.0, y: 0.0, z: -1.0 },
+            Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+            fov,
+        )
+    }
+
+    // Returns (right, up, forward), all normalized and orthogonal
+    pub fn basis(&self) -> (Vector3, Vector3, Vector3) {
+        let forward = (self.target - self.eye).normalize();
+        let right = forward.cross(&self.up).normalize();
+        let up = right.cross(&forward);
+
+        (right, up, forward)
+    }
+
+    // Half extents of the image plane, works for landscape, portrait and square images
+    pub fn sensor_size(&self, width: u32, height: u32) -> (f64, f64) {
+        let fov_adj = (self.fov.to_radians() / 2.0).tan();
+        let aspect_ratio = (width as f64) / (height as f64);
+
+        match self.fov_axis {
+            FovAxis::Horizontal => (fov_adj, fov_adj / aspect_ratio),
+            FovAxis::Vertical => (fov_adj * aspect_ratio, fov_adj),
+        }
+    }
+
+    // Direction through the point (sensor_x, sensor_y) on the image plane one unit in front of the eye
+    pub fn direction(&self, sensor_x: f64, sensor_y: f64) -> Vector3 {
+        let (right, up, forward) = self.basis();
+
+        (right * sensor_x + up * sensor_y + forward).normalize()
+    }
+}
+
+#[test]
+fn test_camera_looks_at_target(){
+    let camera = Camera::new(
+        Point { x: 3.0, y: 2.0, z: 4.0 },
+        Point { x: 0.0, y: 0.0, z: -5.0 },
+        Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+        60.0,
+    );
+
+    let (right, up, _) = camera.basis();
+    let center = camera.direction(0.0, 0.0);
+    let expected = (camera.target - camera.eye).normalize();
+
+    assert!((center - expected).length() < 1e-9);
+    assert!(right.dot(&up).abs() < 1e-9);
+    assert!(up.y > 0.0);
+}
+
+#[test]
+fn test_sensor_size_any_aspect(){
+    let mut camera = Camera::origin(90.0);
+
+    for &(width, height) in &[(800, 600), (600, 800), (512, 512)] {
+        camera.fov_axis = FovAxis::Vertical;
+        let (w, h) = camera.sensor_size(width, height);
+        assert!((h - 1.0).abs() < 1e-9);
+        assert!((w / h - width as f64 / height as f64).abs() < 1e-9);
+
+        camera.fov_axis = FovAxis::Horizontal;
+        let (w, h) = camera.sensor_size(width, height);
+        assert!((w - 1.0).abs() < 1e-9);
+        assert!((w / h - width as f64 / height as f64).abs() < 1e-9);
+    }
+}
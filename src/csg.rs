@@ -0,0 +1,248 @@
+use std::cmp::Ordering;
+use crate::bvh::Aabb;
+use crate::point::Point;
+use crate::vector::Vector3;
+use crate::{Element, Intersectable, Material, Ray, Texture_thing};
+
+// How far behind a hit point the rays finding its surface start
+const PROBE: f64 = 1e-4;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Operation {
+    Union,         // Inside either part
+    Intersection,  // Inside both parts
+    Difference,    // Inside left but not right, right takes a bite out of left
+}
+
+impl Operation {
+    fn contains(self, left: bool, right: bool) -> bool {
+        match self {
+            Operation::Union => left || right,
+            Operation::Intersection => left && right,
+            Operation::Difference => left && !right,
+        }
+    }
+}
+
+// Two elements combined into one solid, shaded with the material of left
+#[derive(Debug)]
+pub struct Csg {
+    pub operation: Operation,
+    pub left: Box<Element>,
+    pub right: Box<Element>,
+}
+
+// Pairs up sorted hits into the stretches between entering and leaving. Closed shapes always
+// give an even count, a ray can't go into one without coming out.
+pub fn pair_hits(hits: &[f64]) -> Vec<(f64, f64)> {
+    debug_assert!(hits.len().is_multiple_of(2), "odd number of hits {:?}", hits);
+    hits.chunks_exact(2).map(|pair| (pair[0], pair[1])).collect()
+}
+
+// Walks along the ray through the edges of both parts and keeps the stretches the operation is inside
+pub fn combine(operation: Operation, left: &[(f64, f64)], right: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    let mut edges = Vec::with_capacity(2 * (left.len() + right.len()));
+    for &(enter, exit) in left.iter() {
+        edges.push((enter, true));
+        edges.push((exit, true));
+    }
+    for &(enter, exit) in right.iter() {
+        edges.push((enter, false));
+        edges.push((exit, false));
+    }
+    edges.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+    let (mut in_left, mut in_right) = (false, false);
+    let mut start = None;
+    let mut intervals = Vec::new();
+    for &(t, is_left) in edges.iter() {
+        if is_left {
+            in_left = !in_left;
+        } else {
+            in_right = !in_right;
+        }
+
+        match (start, operation.contains(in_left, in_right)) {
+            (None, true) => start = Some(t),
+            (Some(enter), false) => {
+                intervals.push((enter, t));
+                start = None;
+            },
+            _ => {},
+        }
+    }
+    intervals
+}
+
+// How far a point is from the surface of an element, along its normal there
+fn distance_to_surface(element: &Element, point: &Point) -> f64 {
+    let normal = element.surface_normal(point);
+    let ray = Ray {
+        origin: *point - normal * PROBE,
+        direction: normal,
+    };
+    element.intervals_within(&ray, 0.0, 2.0 * PROBE).iter()
+        .flat_map(|&(enter, exit)| vec![enter, exit])
+        .map(|t| (t - PROBE).abs())
+        .fold(f64::INFINITY, f64::min)
+}
+
+impl Csg {
+    pub fn material(&self) -> &Material {
+        self.left.material()
+    }
+
+    // None if the result can go on forever
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        let (left, right) = (self.left.bounding_box(), self.right.bounding_box());
+        match self.operation {
+            Operation::Union => Some(left?.union(&right?)),
+            Operation::Intersection => match (left, right) {
+                (Some(l), Some(r)) => Some(l.intersection(&r)),
+                (l, r) => l.or(r),
+            },
+            Operation::Difference => left,
+        }
+    }
+
+    // Which part the hit point is on, and if its normal has to be turned around
+    fn part(&self, hit_point: &Point) -> (&Element, bool) {
+        if distance_to_surface(&self.left, hit_point) <= distance_to_surface(&self.right, hit_point) {
+            (&self.left, false)
+        } else {
+            (&self.right, self.operation == Operation::Difference)
+        }
+    }
+}
+
+impl Intersectable for Csg {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        self.intervals_within(ray, 0.0, f64::INFINITY).iter()
+            .flat_map(|&(enter, exit)| vec![enter, exit])
+            .find(|&t| t >= 0.0 && t.is_finite())
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        let (part, flip) = self.part(hit_point);
+        let normal = part.surface_normal(hit_point);
+        if flip { Vector3::zero() - normal } else { normal }
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> Texture_thing {
+        self.part(hit_point).0.texture_coords(hit_point)
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        self.intervals_within(ray, f64::NEG_INFINITY, f64::INFINITY)
+    }
+
+    fn intervals_within(&self, ray: &Ray, from: f64, to: f64) -> Vec<(f64, f64)> {
+        let left = self.left.intervals_within(ray, from, to);
+        if self.operation == Operation::Union {
+            return combine(self.operation, &left, &self.right.intervals_within(ray, from, to));
+        }
+
+        // Missing left leaves nothing to cut into, no need to look for right. Otherwise right
+        // only matters where left is.
+        let (first, last) = match (left.first(), left.last()) {
+            (Some(first), Some(last)) => (first.0.max(from), last.1.min(to)),
+            _ => return left,
+        };
+        combine(self.operation, &left, &self.right.intervals_within(ray, first, last))
+    }
+}
+
+#[test]
+fn test_combine(){
+    let left = [(0.0, 4.0), (6.0, 8.0)];
+    let right = [(2.0, 7.0)];
+
+    assert_eq!(combine(Operation::Union, &left, &right), vec![(0.0, 8.0)]);
+    assert_eq!(combine(Operation::Intersection, &left, &right), vec![(2.0, 4.0), (6.0, 7.0)]);
+    assert_eq!(combine(Operation::Difference, &left, &right), vec![(0.0, 2.0), (7.0, 8.0)]);
+    assert_eq!(combine(Operation::Difference, &right, &left), vec![(4.0, 6.0)]);
+    assert_eq!(combine(Operation::Intersection, &left, &[]), vec![]);
+
+    assert_eq!(pair_hits(&[1.0, 2.0, 3.0, 4.0]), vec![(1.0, 2.0), (3.0, 4.0)]);
+}
+
+#[test]
+fn test_csg(){
+    use crate::{Color, Sphere};
+
+    let sphere = |x: f64, radius: f64| Element::Sphere(Sphere {
+        center: Point { x, y: 0.0, z: -5.0 },
+        radius,
+        material: Material::diffuse(Color::black()),
+    });
+    let csg = |operation| Csg {
+        operation,
+        left: Box::new(sphere(0.0, 1.0)),
+        right: Box::new(sphere(1.0, 1.0)),
+    };
+    let ray = Ray {
+        origin: Point { x: -5.0, y: 0.0, z: -5.0 },
+        direction: Vector3 { x: 1.0, y: 0.0, z: 0.0 },
+    };
+    let near = |a: f64, b: f64| (a - b).abs() < 1e-9;
+
+    // Along the line through both centers the spheres cover -1..1 and 0..2
+    assert!(near(csg(Operation::Union).intersect(&ray).unwrap(), 4.0));
+    assert!(near(csg(Operation::Intersection).intersect(&ray).unwrap(), 5.0));
+    let bitten = csg(Operation::Difference);
+    assert!(near(bitten.intersect(&ray).unwrap(), 4.0));
+
+    // From the inside of the bite the first surface belongs to right and faces into it
+    let inside = Ray {
+        origin: Point { x: 0.5, y: 0.0, z: -5.0 },
+        direction: Vector3 { x: -1.0, y: 0.0, z: 0.0 },
+    };
+    let t = bitten.intersect(&inside).unwrap();
+    assert!(near(t, 0.5));
+    let normal = bitten.surface_normal(&(inside.origin + inside.direction * t));
+    assert!(near(normal.x, 1.0));
+
+    // A lens doesn't reach past either sphere
+    let lens = csg(Operation::Intersection).bounding_box().unwrap();
+    assert!(near(lens.min.x, 0.0) && near(lens.max.x, 1.0));
+    let bitten = bitten.bounding_box().unwrap();
+    assert!(near(bitten.min.x, -1.0) && near(bitten.max.x, 1.0));
+}
+
+#[test]
+fn test_csg_rim(){
+    use crate::cuboid::Cuboid;
+    use crate::cylinder::Cylinder;
+    use crate::transform::Transform;
+    use crate::Color;
+
+    // A cylinder standing at z = -5, and a box further along a ray that goes in right on its
+    // top rim and out through the side
+    let cylinder = Element::Cylinder(Cylinder {
+        radius: 1.0,
+        height: 2.0,
+        transform: Transform::with_axis(Vector3 { x: 0.0, y: 0.0, z: -5.0 }, &Vector3 { x: 0.0, y: 1.0, z: 0.0 }),
+        material: Material::diffuse(Color::black()),
+    });
+    let ray = Ray {
+        origin: Point { x: 2.0, y: 1.5, z: -5.0 },
+        direction: Vector3 { x: -1.0, y: -0.5, z: 0.0 },
+    };
+    let near = |a: f64, b: f64| (a - b).abs() < 1e-9;
+    let intervals = cylinder.intervals(&ray);
+    assert_eq!(intervals.len(), 1);
+    assert!(near(intervals[0].0, 1.0) && near(intervals[0].1, 3.0));
+
+    // Taking the cylinder away leaves the box behind it alone
+    let bitten = Csg {
+        operation: Operation::Difference,
+        left: Box::new(Element::Box(Cuboid {
+            min: Point { x: -5.0, y: -2.0, z: -6.0 },
+            max: Point { x: -3.5, y: -1.0, z: -4.0 },
+            transform: None,
+            material: Material::diffuse(Color::black()),
+        })),
+        right: Box::new(cylinder),
+    };
+    assert!(near(bitten.intersect(&ray).unwrap(), 5.5));
+}
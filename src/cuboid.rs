@@ -0,0 +1,185 @@
+use crate::bvh::Aabb;
+use crate::point::Point;
+use crate::transform::Transform;
+use crate::vector::Vector3;
+use crate::{Intersectable, Material, Ray, Texture_thing};
+
+// Box between min and max. Without a transform it is lined up with the world axes,
+// with one min and max are in the box's own space and the transform places it.
+#[derive(Debug)]
+pub struct Cuboid {
+    pub min: Point,
+    pub max: Point,
+    pub transform: Option<Transform>,
+    pub material: Material,
+}
+
+fn coords(p: &Point) -> [f64; 3] {
+    [p.x, p.y, p.z]
+}
+
+impl Cuboid {
+    fn local_ray(&self, ray: &Ray) -> Ray {
+        match self.transform {
+            Some(ref t) => t.ray_to_local(ray),
+            None => Ray { origin: ray.origin, direction: ray.direction },
+        }
+    }
+
+    fn local_point(&self, p: &Point) -> Point {
+        self.transform.map_or(*p, |t| t.point_to_local(p))
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        let local = Aabb { min: self.min, max: self.max };
+        self.transform.map_or(local, |t| t.bounds_to_world(&local))
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.min.is_finite() && self.max.is_finite() && self.min.x < self.max.x && self.min.y < self.max.y && self.min.z < self.max.z
+    }
+
+    // Distances where the ray goes in and comes out again, the part behind the origin included
+    pub fn interval(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let ray = self.local_ray(ray);
+        let (min, max) = (coords(&self.min), coords(&self.max));
+        let (origin, direction) = (coords(&ray.origin), [ray.direction.x, ray.direction.y, ray.direction.z]);
+
+        let mut near = f64::NEG_INFINITY;
+        let mut far = f64::INFINITY;
+        for i in 0..3 {
+            if direction[i] == 0.0 {
+                // Parallel to this pair of faces, either always between them or never
+                if origin[i] < min[i] || origin[i] > max[i] {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (min[i] - origin[i]) / direction[i];
+            let t1 = (max[i] - origin[i]) / direction[i];
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+        }
+
+        if near <= far { Some((near, far)) } else { None }
+    }
+
+    // Axis (0, 1 or 2) and side (-1 or 1) of the face a local point is on
+    fn face(&self, local: &Point) -> (usize, f64) {
+        let (p, min, max) = (coords(local), coords(&self.min), coords(&self.max));
+        let mut best = (0, -1.0, f64::INFINITY);
+        for i in 0..3 {
+            let size = max[i] - min[i];
+            let to_min = (p[i] - min[i]).abs() / size;
+            let to_max = (p[i] - max[i]).abs() / size;
+            if to_min < best.2 {
+                best = (i, -1.0, to_min);
+            }
+            if to_max < best.2 {
+                best = (i, 1.0, to_max);
+            }
+        }
+        (best.0, best.1)
+    }
+}
+
+impl Intersectable for Cuboid {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let (near, far) = self.interval(ray)?;
+        if near >= 0.0 {
+            Some(near)
+        } else if far >= 0.0 {
+            Some(far)
+        } else {
+            None
+        }
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        let (axis, side) = self.face(&self.local_point(hit_point));
+        let mut normal = [0.0; 3];
+        normal[axis] = side;
+        let normal = Vector3 { x: normal[0], y: normal[1], z: normal[2] };
+        self.transform.map_or(normal, |t| t.direction_to_world(&normal))
+    }
+
+    // Every face gets the whole texture once
+    fn texture_coords(&self, hit_point: &Point) -> Texture_thing {
+        let local = self.local_point(hit_point);
+        let (axis, side) = self.face(&local);
+        let (p, min, max) = (coords(&local), coords(&self.min), coords(&self.max));
+        let along = |i: usize| ((p[i] - min[i]) / (max[i] - min[i])) as f32;
+
+        // Seen from outside every face has u going right and v going up, so no face is mirrored
+        let (u, v) = match (axis, side > 0.0) {
+            (0, true) => (1.0 - along(2), along(1)),
+            (0, false) => (along(2), along(1)),
+            (1, true) => (along(0), 1.0 - along(2)),
+            (1, false) => (along(0), along(2)),
+            (_, true) => (along(0), along(1)),
+            (_, false) => (1.0 - along(0), along(1)),
+        };
+        Texture_thing {
+            x: u,
+            y: 1.0 - v,
+        }
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        self.interval(ray).into_iter().collect()
+    }
+}
+
+#[test]
+fn test_cuboid(){
+    use crate::Color;
+
+    let material = || Material::diffuse(Color::black());
+
+    let cuboid = Cuboid {
+        min: Point { x: -1.0, y: -1.0, z: -6.0 },
+        max: Point { x: 1.0, y: 2.0, z: -4.0 },
+        transform: None,
+        material: material(),
+    };
+    let ray = Ray { origin: Point::zero(), direction: Vector3 { x: 0.0, y: 0.0, z: -1.0 } };
+    assert_eq!(cuboid.intersect(&ray), Some(4.0));
+    let hit_point = Point { x: 0.5, y: 0.5, z: -4.0 };
+    assert_eq!(cuboid.surface_normal(&hit_point).z, 1.0);
+    let coords = cuboid.texture_coords(&hit_point);
+    assert_eq!((coords.x, coords.y), (0.75, 0.5));
+
+    // The back is seen from behind, so right there is towards -x
+    let coords = cuboid.texture_coords(&Point { x: 0.5, y: 0.5, z: -6.0 });
+    assert_eq!((coords.x, coords.y), (0.25, 0.5));
+    // Right on the +x side is towards -z and on the -x side towards +z
+    let coords = cuboid.texture_coords(&Point { x: 1.0, y: 0.5, z: -4.5 });
+    assert_eq!((coords.x, coords.y), (0.25, 0.5));
+    let coords = cuboid.texture_coords(&Point { x: -1.0, y: 0.5, z: -4.5 });
+    assert_eq!((coords.x, coords.y), (0.75, 0.5));
+    // The top has -z up and the bottom +z up
+    let coords = cuboid.texture_coords(&Point { x: 0.5, y: 2.0, z: -4.5 });
+    assert_eq!((coords.x, coords.y), (0.75, 0.75));
+    let coords = cuboid.texture_coords(&Point { x: 0.5, y: -1.0, z: -4.5 });
+    assert_eq!((coords.x, coords.y), (0.75, 0.25));
+
+    // From the inside the way out is the hit
+    let inside = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector3 { x: 1.0, y: 0.0, z: 0.0 } };
+    assert_eq!(cuboid.intersect(&inside), Some(1.0));
+    let miss = Ray { origin: Point::zero(), direction: Vector3 { x: 0.0, y: 1.0, z: 0.0 } };
+    assert_eq!(cuboid.intersect(&miss), None);
+
+    // A 2x2x2 box turned 45 degrees around y, its corner points at the camera
+    let turned = Cuboid {
+        min: Point::from_one(-1.0),
+        max: Point::from_one(1.0),
+        transform: Some(Transform::new(Vector3 { x: 0.0, y: 0.0, z: -5.0 }, Vector3 { x: 0.0, y: 45.0, z: 0.0 })),
+        material: material(),
+    };
+    let distance = turned.intersect(&ray).unwrap();
+    assert!((distance - (5.0 - 2f64.sqrt())).abs() < 1e-9);
+    let bounds = turned.bounding_box();
+    assert!((bounds.max.x - 2f64.sqrt()).abs() < 1e-9 && (bounds.max.y - 1.0).abs() < 1e-9);
+    let normal = turned.surface_normal(&Point { x: 0.1, y: 0.0, z: -5.0 + 2f64.sqrt() - 0.1 });
+    assert!((normal.length() - 1.0).abs() < 1e-9 && normal.z > 0.5 && normal.x > 0.5);
+}
@@ -0,0 +1,365 @@
+use std::cmp::Ordering;
+use std::f64::consts::PI;
+use crate::bvh::Aabb;
+use crate::csg::pair_hits;
+use crate::point::Point;
+use crate::transform::Transform;
+use crate::vector::Vector3;
+use crate::{Intersectable, Material, Ray, Texture_thing};
+
+// Round shapes around an axis. In their own space the axis is +y and they are centered on the
+// origin, the transform puts them in the world.
+
+// Capped cylinder
+#[derive(Debug)]
+pub struct Cylinder {
+    pub radius: f64,
+    pub height: f64,
+    pub transform: Transform,
+    pub material: Material,
+}
+
+// Capped cone, radius at the bottom and top_radius at the top, 0 makes a point
+#[derive(Debug)]
+pub struct Cone {
+    pub radius: f64,
+    pub top_radius: f64,
+    pub height: f64,
+    pub transform: Transform,
+    pub material: Material,
+}
+
+// Flat and round, lit on both sides
+#[derive(Debug)]
+pub struct Disk {
+    pub center: Point,
+    pub normal: Vector3,
+    pub radius: f64,
+    pub material: Material,
+}
+
+// Both cylinders and cones, the radius changes linearly from bottom to top
+struct Frustum {
+    bottom: f64,
+    top: f64,
+    height: f64,
+}
+
+impl Frustum {
+    fn slope(&self) -> f64 {
+        (self.top - self.bottom) / self.height
+    }
+
+    fn radius_at(&self, y: f64) -> f64 {
+        self.bottom + self.slope() * (y + self.height / 2.0)
+    }
+
+    fn bounds(&self) -> Aabb {
+        let r = self.bottom.max(self.top);
+        Aabb {
+            min: Point { x: -r, y: -self.height / 2.0, z: -r },
+            max: Point { x: r, y: self.height / 2.0, z: r },
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.bottom >= 0.0 && self.top >= 0.0 && self.bottom.max(self.top) > 0.0 && self.height > 0.0
+            && self.bottom.is_finite() && self.top.is_finite() && self.height.is_finite()
+    }
+
+    // Where the local ray goes in and where it comes out again. The shape is convex, so that's
+    // the part between the caps that is also inside the side. Working it out that way instead of
+    // collecting the crossings of each part means a ray through the rim can't be counted twice.
+    fn hits(&self, ray: &Ray) -> Vec<f64> {
+        let (o, d) = (ray.origin, ray.direction);
+        let half = self.height / 2.0;
+        let k = self.slope();
+        let middle = self.radius_at(0.0);
+        let (inf, neg_inf) = (f64::INFINITY, f64::NEG_INFINITY);
+
+        let (enter, exit) = if d.y != 0.0 {
+            let (t0, t1) = ((-half - o.y) / d.y, (half - o.y) / d.y);
+            (t0.min(t1), t0.max(t1))
+        } else if o.y.abs() <= half {
+            (neg_inf, inf)
+        } else {
+            return Vec::new();
+        };
+
+        // Inside the side where x² + z² <= (middle + k y)²
+        let a = d.x * d.x + d.z * d.z - k * k * d.y * d.y;
+        let b = 2.0 * (o.x * d.x + o.z * d.z - k * d.y * (middle + k * o.y));
+        let c = o.x * o.x + o.z * o.z - (middle + k * o.y) * (middle + k * o.y);
+        let inside = if a.abs() > 1e-12 {
+            let discriminant = b * b - 4.0 * a * c;
+            if discriminant < 0.0 {
+                if a > 0.0 { vec![] } else { vec![(neg_inf, inf)] }
+            } else {
+                // The stable form, avoids cancelling when b is much larger than the rest
+                let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+                let (t0, t1) = if q == 0.0 { (0.0, 0.0) } else { (q / a, c / q) };
+                let (t0, t1) = (t0.min(t1), t0.max(t1));
+                // Steep rays are inside the double cone before the first root and after the second
+                if a > 0.0 { vec![(t0, t1)] } else { vec![(neg_inf, t0), (t1, inf)] }
+            }
+        } else if b.abs() > 1e-12 {
+            let t = -c / b;
+            if b > 0.0 { vec![(neg_inf, t)] } else { vec![(t, inf)] }
+        } else if c <= 0.0 {
+            vec![(neg_inf, inf)]
+        } else {
+            vec![]
+        };
+
+        // The other half of a cone only reaches the caps at its point, the longest part is the real one
+        inside.into_iter()
+            .map(|(from, to)| (from.max(enter), to.min(exit)))
+            .filter(|&(from, to)| from <= to)
+            .max_by(|x, y| (x.1 - x.0).partial_cmp(&(y.1 - y.0)).unwrap_or(Ordering::Equal))
+            .map_or(Vec::new(), |(from, to)| vec![from, to])
+    }
+
+    // 0 for the side, -1 or 1 for the bottom or top cap
+    fn part(&self, p: &Point) -> i32 {
+        let half = self.height / 2.0;
+        let to_side = ((p.x * p.x + p.z * p.z).sqrt() - self.radius_at(p.y)).abs();
+        let to_bottom = (p.y + half).abs();
+        let to_top = (p.y - half).abs();
+
+        if to_side <= to_bottom && to_side <= to_top {
+            0
+        } else if to_bottom < to_top {
+            -1
+        } else {
+            1
+        }
+    }
+
+    fn normal(&self, p: &Point) -> Vector3 {
+        match self.part(p) {
+            0 => Vector3 { x: p.x, y: -self.slope() * self.radius_at(p.y), z: p.z }.normalize(),
+            cap => Vector3 { x: 0.0, y: cap as f64, z: 0.0 },
+        }
+    }
+
+    // Around the side and up for the side, around and out from the middle on the caps
+    fn texture_coords(&self, p: &Point) -> Texture_thing {
+        let around = (0.5 + p.z.atan2(p.x) / (2.0 * PI)) as f32;
+        match self.part(p) {
+            0 => Texture_thing { x: around, y: (0.5 - p.y / self.height) as f32 },
+            cap => {
+                let r = if cap < 0 { self.bottom } else { self.top };
+                Texture_thing { x: around, y: ((p.x * p.x + p.z * p.z).sqrt() / r) as f32 }
+            }
+        }
+    }
+}
+
+fn first_hit(hits: &[f64]) -> Option<f64> {
+    hits.iter().cloned().find(|&t| t >= 0.0)
+}
+
+impl Cylinder {
+    fn frustum(&self) -> Frustum {
+        Frustum { bottom: self.radius, top: self.radius, height: self.height }
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        self.transform.bounds_to_world(&self.frustum().bounds())
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.radius > 0.0 && self.frustum().is_valid() && self.transform.is_finite()
+    }
+
+    pub fn hits(&self, ray: &Ray) -> Vec<f64> {
+        self.frustum().hits(&self.transform.ray_to_local(ray))
+    }
+}
+
+impl Intersectable for Cylinder {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        first_hit(&self.hits(ray))
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        pair_hits(&self.hits(ray))
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        self.transform.direction_to_world(&self.frustum().normal(&self.transform.point_to_local(hit_point)))
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> Texture_thing {
+        self.frustum().texture_coords(&self.transform.point_to_local(hit_point))
+    }
+}
+
+impl Cone {
+    fn frustum(&self) -> Frustum {
+        Frustum { bottom: self.radius, top: self.top_radius, height: self.height }
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        self.transform.bounds_to_world(&self.frustum().bounds())
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.frustum().is_valid() && self.transform.is_finite()
+    }
+
+    pub fn hits(&self, ray: &Ray) -> Vec<f64> {
+        self.frustum().hits(&self.transform.ray_to_local(ray))
+    }
+}
+
+impl Intersectable for Cone {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        first_hit(&self.hits(ray))
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        pair_hits(&self.hits(ray))
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        self.transform.direction_to_world(&self.frustum().normal(&self.transform.point_to_local(hit_point)))
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> Texture_thing {
+        self.frustum().texture_coords(&self.transform.point_to_local(hit_point))
+    }
+}
+
+impl Disk {
+    // Lit on both sides, so the normal turns towards where the ray came from
+    pub fn facing_normal(&self, hit_point: &Point, direction: &Vector3) -> Vector3 {
+        let normal = self.surface_normal(hit_point);
+        if normal.dot(direction) > 0.0 {
+            Vector3::zero() - normal
+        } else {
+            normal
+        }
+    }
+
+    fn transform(&self) -> Transform {
+        Transform::with_axis(self.center - Point::zero(), &self.normal)
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        let local = Aabb {
+            min: Point { x: -self.radius, y: 0.0, z: -self.radius },
+            max: Point { x: self.radius, y: 0.0, z: self.radius },
+        };
+        self.transform().bounds_to_world(&local)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.center.is_finite() && self.normal.is_finite() && self.normal.length() > 0.0 && self.radius > 0.0 && self.radius.is_finite()
+    }
+}
+
+impl Intersectable for Disk {
+    // Hit from both sides
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let normal = self.normal.normalize();
+        let denom = normal.dot(&ray.direction);
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+
+        let distance = (self.center - ray.origin).dot(&normal) / denom;
+        let hit_point = ray.origin + ray.direction * distance;
+        if distance >= 0.0 && (hit_point - self.center).norm() <= self.radius * self.radius {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+
+    fn surface_normal(&self, _: &Point) -> Vector3 {
+        self.normal.normalize()
+    }
+
+    // Polar, around the center and then out to the edge
+    fn texture_coords(&self, hit_point: &Point) -> Texture_thing {
+        let p = self.transform().point_to_local(hit_point);
+        Texture_thing {
+            x: (0.5 + p.z.atan2(p.x) / (2.0 * PI)) as f32,
+            y: ((p.x * p.x + p.z * p.z).sqrt() / self.radius) as f32,
+        }
+    }
+}
+
+#[test]
+fn test_round_shapes(){
+    use crate::Color;
+
+    let material = || Material::diffuse(Color::black());
+    let down_z = |x: f64, y: f64| Ray { origin: Point { x, y, z: 0.0 }, direction: Vector3 { x: 0.0, y: 0.0, z: -1.0 } };
+    let up = Vector3 { x: 0.0, y: 1.0, z: 0.0 };
+
+    // Standing up at z = -5, 2 high
+    let cylinder = Cylinder {
+        radius: 1.0,
+        height: 2.0,
+        transform: Transform::with_axis(Vector3 { x: 0.0, y: 0.0, z: -5.0 }, &up),
+        material: material(),
+    };
+    assert!((cylinder.intersect(&down_z(0.0, 0.0)).unwrap() - 4.0).abs() < 1e-9);
+    assert_eq!(cylinder.intersect(&down_z(0.0, 1.5)), None);
+    assert_eq!(cylinder.hits(&down_z(0.0, 0.5)).len(), 2);
+    let normal = cylinder.surface_normal(&Point { x: 0.0, y: 0.5, z: -4.0 });
+    assert!((normal - Vector3 { x: 0.0, y: 0.0, z: 1.0 }).length() < 1e-9);
+
+    // Looking down onto the top cap
+    let from_above = Ray { origin: Point { x: 0.5, y: 5.0, z: -5.0 }, direction: Vector3 { x: 0.0, y: -1.0, z: 0.0 } };
+    assert!((cylinder.intersect(&from_above).unwrap() - 4.0).abs() < 1e-9);
+    let top = Point { x: 0.5, y: 1.0, z: -5.0 };
+    assert!((cylinder.surface_normal(&top) - up).length() < 1e-9);
+    assert!((cylinder.texture_coords(&top).y - 0.5).abs() < 1e-6);
+
+    // Pointy cone, the ray through the middle of its height goes in at half the radius
+    let cone = Cone {
+        radius: 1.0,
+        top_radius: 0.0,
+        height: 2.0,
+        transform: Transform::with_axis(Vector3 { x: 0.0, y: 0.0, z: -5.0 }, &up),
+        material: material(),
+    };
+    assert!((cone.intersect(&down_z(0.0, 0.0)).unwrap() - 4.5).abs() < 1e-9);
+    assert_eq!(cone.intersect(&down_z(0.0, 1.1)), None);
+    let side = cone.surface_normal(&Point { x: 0.0, y: 0.0, z: -4.5 });
+    assert!(side.y > 0.0 && side.z > 0.0 && (side.length() - 1.0).abs() < 1e-9);
+
+    let disk = Disk {
+        center: Point { x: 0.0, y: 0.0, z: -3.0 },
+        normal: Vector3 { x: 0.0, y: 0.0, z: 2.0 },
+        radius: 1.0,
+        material: material(),
+    };
+    assert_eq!(disk.intersect(&down_z(0.5, 0.5)), Some(3.0));
+    assert_eq!(disk.intersect(&down_z(0.8, 0.8)), None);
+    assert!((disk.texture_coords(&Point { x: 0.5, y: 0.0, z: -3.0 }).y - 0.5).abs() < 1e-6);
+    let bounds = disk.bounding_box();
+    assert!((bounds.max.x - 1.0).abs() < 1e-9 && (bounds.min.z + 3.0).abs() < 1e-9 && (bounds.max.z + 3.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_disk_lit_from_both_sides(){
+    use std::path::Path;
+
+    // The light shines the same way the camera looks, whichever way the disk faces it should be lit
+    let text = r#"{ "width": 10, "height": 10,
+        "elements": [
+            { "type": "disk", "center": [0, 0, -3], "normal": [0, 0, 1], "radius": 1, "material": { "color": [1, 1, 1] } }
+        ],
+        "lights": [{ "type": "directional", "direction": [0, 0, -1], "color": [1, 1, 1], "intensity": 1 }] }"#;
+    let ray = Ray { origin: Point::zero(), direction: Vector3 { x: 0.0, y: 0.0, z: -1.0 } };
+    let facing = crate::parse_scene(text, "test.json", Path::new(".")).unwrap();
+    let turned = crate::parse_scene(&text.replace("[0, 0, 1]", "[0, 0, -1]"), "test.json", Path::new(".")).unwrap();
+
+    let lit = crate::raycast(&facing, &ray, 0);
+    assert!(lit.red > 0.0);
+    assert_eq!(crate::raycast(&turned, &ray, 0).red, lit.red);
+}
@@ -0,0 +1,32 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum RenderError {
+    Io { path: String, error: io::Error },
+    Parse { file: String, line: usize, message: String },
+    MissingTexture { path: String, message: String },
+    InvalidGeometry(String),
+    Cancelled,
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RenderError::Io { ref path, ref error } => write!(f, "{}: {}", path, error),
+            RenderError::Parse { ref file, line, ref message } => write!(f, "{}:{}: {}", file, line, message),
+            RenderError::MissingTexture { ref path, ref message } => write!(f, "could not load texture {}: {}", path, message),
+            RenderError::InvalidGeometry(ref message) => write!(f, "invalid geometry: {}", message),
+            RenderError::Cancelled => write!(f, "render was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            RenderError::Io { ref error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
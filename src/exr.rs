@@ -0,0 +1,114 @@
+use std::io::{self, Write};
+use crate::framebuffer::Framebuffer;
+
+// Smallest OpenEXR file that other tools read: one part, scanlines, no compression and
+// 32-bit float B, G and R channels (they have to be sorted by name)
+
+const MAGIC: u32 = 20000630;
+const VERSION: u32 = 2;
+const FLOAT: i32 = 2;
+const CHANNELS: [&str; 3] = ["B", "G", "R"];
+
+fn attribute<W: Write>(out: &mut W, name: &str, kind: &str, value: &[u8]) -> io::Result<()> {
+    out.write_all(name.as_bytes())?;
+    out.write_all(&[0])?;
+    out.write_all(kind.as_bytes())?;
+    out.write_all(&[0])?;
+    out.write_all(&(value.len() as i32).to_le_bytes())?;
+    out.write_all(value)
+}
+
+fn header(framebuffer: &Framebuffer) -> Vec<u8> {
+    let mut header = Vec::new();
+
+    let mut channels = Vec::new();
+    for name in CHANNELS.iter() {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&FLOAT.to_le_bytes());
+        channels.extend_from_slice(&[0, 0, 0, 0]);  // pLinear and reserved
+        channels.extend_from_slice(&1i32.to_le_bytes());  // x and y sampling
+        channels.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channels.push(0);
+
+    let mut window = Vec::new();
+    for v in [0, 0, framebuffer.width as i32 - 1, framebuffer.height as i32 - 1].iter() {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+
+    // Writing to a Vec can't fail
+    let mut add = |name: &str, kind: &str, value: &[u8]| attribute(&mut header, name, kind, value).unwrap();
+    add("channels", "chlist", &channels);
+    add("compression", "compression", &[0]);
+    add("dataWindow", "box2i", &window);
+    add("displayWindow", "box2i", &window);
+    add("lineOrder", "lineOrder", &[0]);
+    add("pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+    add("screenWindowCenter", "v2f", &[0; 8]);
+    add("screenWindowWidth", "float", &1.0f32.to_le_bytes());
+    header.push(0);
+
+    header
+}
+
+pub fn write_exr<W: Write>(framebuffer: &Framebuffer, out: &mut W) -> io::Result<()> {
+    let header = header(framebuffer);
+    let line_size = 8 + framebuffer.width as u64 * CHANNELS.len() as u64 * 4;
+
+    out.write_all(&MAGIC.to_le_bytes())?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    out.write_all(&header)?;
+
+    // Where every scanline starts, counted from the start of the file
+    let first_line = 8 + header.len() as u64 + framebuffer.height as u64 * 8;
+    for y in 0..framebuffer.height as u64 {
+        out.write_all(&(first_line + y * line_size).to_le_bytes())?;
+    }
+
+    for y in 0..framebuffer.height {
+        out.write_all(&(y as i32).to_le_bytes())?;
+        out.write_all(&((line_size - 8) as i32).to_le_bytes())?;
+        for channel in CHANNELS.iter() {
+            for x in 0..framebuffer.width {
+                let color = framebuffer.get(x, y);
+                let value = match *channel {
+                    "B" => color.blue,
+                    "G" => color.green,
+                    _ => color.red,
+                };
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_write_exr(){
+    use crate::Color;
+
+    let mut framebuffer = Framebuffer::new(3, 2);
+    framebuffer.set(2, 1, Color { red: 12.5, green: 0.25, blue: -1.0 });
+
+    let mut file = Vec::new();
+    write_exr(&framebuffer, &mut file).unwrap();
+
+    let read_u32 = |at: usize| u32::from_le_bytes([file[at], file[at + 1], file[at + 2], file[at + 3]]);
+    let read_f32 = |at: usize| f32::from_bits(read_u32(at));
+    assert_eq!(read_u32(0), MAGIC);
+    assert_eq!(read_u32(4), 2);
+
+    let offsets = 8 + header(&framebuffer).len();
+    let last_line = read_u32(offsets + 8) as usize;
+    assert_eq!(read_u32(last_line), 1);
+    assert_eq!(read_u32(last_line + 4), 3 * 3 * 4);
+    assert_eq!(last_line + 8 + 3 * 3 * 4, file.len());
+
+    // B, G and R after each other, the pixel is the third in every channel
+    let pixel = last_line + 8 + 2 * 4;
+    assert_eq!(read_f32(pixel), -1.0);
+    assert_eq!(read_f32(pixel + 12), 0.25);
+    assert_eq!(read_f32(pixel + 24), 12.5);
+}
@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use image::codecs::hdr::HdrEncoder;
+use image::{DynamicImage, GenericImage, Rgb};
+use crate::error::RenderError;
+use crate::exr::write_exr;
+use crate::Color;
+
+// How linear colors above 1 are squeezed into what an 8-bit image can show
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ToneMap {
+    Clamp,     // Anything above 1 is white, same as before there was a framebuffer
+    Reinhard,  // x / (1 + x), never quite white
+    Aces,      // Filmic curve fitted to ACES, more contrast than Reinhard
+}
+
+impl ToneMap {
+    // exposure is in stops, every +1 doubles the light before the curve
+    pub fn apply(&self, color: &Color, exposure: f32) -> Color {
+        let scale = exposure.exp2();
+        let curve = |x: f32| {
+            let x = (x * scale).max(0.0);
+            match *self {
+                ToneMap::Clamp => x,
+                ToneMap::Reinhard => x / (1.0 + x),
+                ToneMap::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+            }
+        };
+
+        Color {
+            red: curve(color.red),
+            green: curve(color.green),
+            blue: curve(color.blue),
+        }.clamp()
+    }
+}
+
+// What a render is saved as
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OutputFormat {
+    Image,  // Tone mapped 8-bit, png, jpg or whatever else the image crate knows from the extension
+    Exr,    // OpenEXR with 32-bit floats, straight from the framebuffer
+    Hdr,    // Radiance .hdr, also the linear colors but only positive ones
+}
+
+impl OutputFormat {
+    pub fn from_path(path: &Path) -> OutputFormat {
+        match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("exr") => OutputFormat::Exr,
+            Some("hdr") => OutputFormat::Hdr,
+            _ => OutputFormat::Image,
+        }
+    }
+}
+
+// Unclamped linear colors of a whole render
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Color::black(); (width * height) as usize],
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> &Color {
+        &self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, color: Color) {
+        self.pixels[(y * self.width + x) as usize] = color;
+    }
+
+    pub fn to_image(&self, tone_map: ToneMap, exposure: f32) -> DynamicImage {
+        let mut img = DynamicImage::new_rgb8(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                img.put_pixel(x, y, tone_map.apply(self.get(x, y), exposure).to_rgba());
+            }
+        }
+        img
+    }
+
+    pub fn write_hdr<W: Write>(&self, out: W) -> io::Result<()> {
+        let pixels: Vec<Rgb<f32>> = self.pixels.iter()
+            .map(|c| Rgb([c.red.max(0.0), c.green.max(0.0), c.blue.max(0.0)]))
+            .collect();
+        HdrEncoder::new(out)
+            .encode(&pixels, self.width as usize, self.height as usize)
+            .map_err(io::Error::other)
+    }
+
+    // tone_map and exposure are only used for 8-bit images, the float formats get the linear colors
+    pub fn save(&self, path: &Path, format: OutputFormat, tone_map: ToneMap, exposure: f32) -> Result<(), RenderError> {
+        let io_error = |error: io::Error| RenderError::Io { path: path.display().to_string(), error };
+
+        match format {
+            OutputFormat::Image => self.to_image(tone_map, exposure).save(path).map_err(|e| io_error(io::Error::other(e))),
+            OutputFormat::Exr | OutputFormat::Hdr => {
+                let mut out = BufWriter::new(File::create(path).map_err(io_error)?);
+                if format == OutputFormat::Exr {
+                    write_exr(self, &mut out).map_err(io_error)?;
+                } else {
+                    self.write_hdr(&mut out).map_err(io_error)?;
+                }
+                out.flush().map_err(io_error)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_tone_map(){
+    let bright = Color { red: 4.0, green: 1.0, blue: 0.0 };
+
+    let clamped = ToneMap::Clamp.apply(&bright, 0.0);
+    assert_eq!((clamped.red, clamped.green, clamped.blue), (1.0, 1.0, 0.0));
+    assert_eq!(ToneMap::Clamp.apply(&bright, -2.0).red, 1.0);
+    assert_eq!(ToneMap::Clamp.apply(&bright, -3.0).red, 0.5);
+
+    let reinhard = ToneMap::Reinhard.apply(&bright, 0.0);
+    assert_eq!((reinhard.red, reinhard.green), (0.8, 0.5));
+
+    // Brighter stays brighter and nothing goes past white
+    let aces = |x: f32| ToneMap::Aces.apply(&Color { red: x, green: 0.0, blue: 0.0 }, 0.0).red;
+    assert_eq!(aces(0.0), 0.0);
+    assert!(aces(0.5) < aces(1.0) && aces(1.0) < aces(4.0) && aces(100.0) <= 1.0);
+
+    let mut framebuffer = Framebuffer::new(2, 1);
+    framebuffer.set(1, 0, bright);
+    let img = framebuffer.to_image(ToneMap::Clamp, 0.0).to_rgb8();
+    assert_eq!(img.get_pixel(0, 0).0, [0, 0, 0]);
+    assert_eq!(img.get_pixel(1, 0).0, [255, 255, 0]);
+
+    let mut hdr = Vec::new();
+    framebuffer.write_hdr(&mut hdr).unwrap();
+    let decoded = image::codecs::hdr::HdrDecoder::new(&hdr[..]).unwrap().read_image_hdr().unwrap();
+    assert_eq!(decoded[1].0, [4.0, 1.0, 0.0]);
+
+    assert_eq!(OutputFormat::from_path(Path::new("out/render.EXR")), OutputFormat::Exr);
+    assert_eq!(OutputFormat::from_path(Path::new("render.hdr")), OutputFormat::Hdr);
+    assert_eq!(OutputFormat::from_path(Path::new("render.png")), OutputFormat::Image);
+}
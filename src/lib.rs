@@ -1,30 +1,76 @@
 #![crate_type = "cdylib"]
+#![allow(non_camel_case_types)]
 extern crate jni;
 use std::path::Path;
-use std::path::PathBuf;
-use image::{DynamicImage, GenericImage, Pixel, Rgba};
-use std::ops::{Add, Sub, Mul, Neg};
+use image::{DynamicImage, Pixel, Rgba};
+use std::ops::{Add, Mul};
 use image::GenericImageView;
-use image;
+mod area_light;
+mod background;
+mod bvh;
+mod camera;
+mod csg;
+mod cuboid;
+mod cylinder;
+mod error;
+mod exr;
+mod framebuffer;
+mod mesh;
+mod path_tracer;
 mod point;
+mod progress;
+mod sampling;
+mod scene_file;
+mod sdf;
+mod torus;
+mod transform;
 mod vector;
+pub use crate::area_light::{AreaLight, AreaShape};
+use crate::area_light::{can_sample, sample_emitter};
+pub use crate::background::{Background, EnvironmentMap};
+use crate::bvh::{Aabb, Bvh};
+pub use crate::camera::{Camera, FovAxis};
+pub use crate::csg::{Csg, Operation};
+pub use crate::cuboid::Cuboid;
+pub use crate::cylinder::{Cone, Cylinder, Disk};
+pub use crate::error::RenderError;
+pub use crate::framebuffer::{Framebuffer, OutputFormat, ToneMap};
+use crate::mesh::Triangle;
+pub use crate::path_tracer::Integrator;
+use crate::path_tracer::trace_path;
 use crate::point::Point;
+pub use crate::progress::{CancelToken, Progress};
+use crate::sampling::{cosine_hemisphere, pixel_offsets, Rng};
+pub use crate::sampling::Sampling;
+pub use crate::scene_file::{load_scene, parse_scene};
+pub use crate::sdf::{Sdf, SdfShape};
+pub use crate::torus::Torus;
+pub use crate::transform::Transform;
 use crate::vector::Vector3;
-use image::io::Reader as ImageReader;
 use jni::JNIEnv;
-use jni::objects::{JClass, JString};
-use jni::sys::jstring;
-use std::io::{BufRead, BufReader};
-use std::fs::File;
-
-const GAMMA: f32 = 1.5;
-
-fn encode_gamma(linear: f32) -> f32 {
-    linear.powf(1.0 / GAMMA)
+use jni::objects::{JClass, JObject, JString, JValue};
+use jni::sys::{jint, jintArray};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Instant;
+
+// The sRGB curve, linear near black and a 2.4 power above
+fn encode_srgb(linear: f32) -> f32 {
+    if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
 }
 
-fn decode_gamma(encoded: f32) -> f32 {
-    encoded.powf(GAMMA)
+fn decode_srgb(encoded: f32) -> f32 {
+    if encoded <= 0.040_45 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
 }
 
 pub struct Ray {
@@ -34,27 +80,20 @@ pub struct Ray {
 
 impl Ray{
 
-    pub fn create_prime_old(x:u32, y:u32, scene: &Scene) -> Ray{
-        Ray{
-            origin: Point::zero(),
-            direction: Vector3::zero(),
-        }
-    }
     pub fn create_prime(x: u32, y:u32, scene: &Scene) -> Ray{
-        assert!(scene.width > scene.height);
-        let fov_adj = (scene.fov.to_radians() / 2.0).tan();
-        let aspect_ratio = (scene.width as f64) / (scene.height as f64);
-        let sensor_x = (((x as f64 + 0.5) / scene.width as f64) * 2.0 - 1.0) * aspect_ratio;
-        let sensor_y = 1.0 - ((y as f64 + 0.5) / scene.height as f64) * 2.0;
+        Ray::create_prime_sample(x, y, 0.5, 0.5, scene)
+    }
+
+    // offset_x and offset_y in [0, 1) pick where inside the pixel the ray goes
+    pub fn create_prime_sample(x: u32, y: u32, offset_x: f64, offset_y: f64, scene: &Scene) -> Ray{
+        let camera = &scene.camera;
+        let (sensor_width, sensor_height) = camera.sensor_size(scene.width, scene.height);
+        let sensor_x = (((x as f64 + offset_x) / scene.width as f64) * 2.0 - 1.0) * sensor_width;
+        let sensor_y = (1.0 - ((y as f64 + offset_y) / scene.height as f64) * 2.0) * sensor_height;
 
         Ray{
-            origin: Point::zero(),
-            direction: Vector3 {
-                x: sensor_x,
-                y: sensor_y,
-                z: -1.0,
-            }
-            .normalize(),
+            origin: camera.eye,
+            direction: camera.direction(sensor_x, sensor_y),
         }
 
     }
@@ -115,9 +154,46 @@ pub struct PointLight{
     pub intensity: f32,
 }
 
+// Point light that only shines in a cone, fading out between inner_angle and outer_angle (degrees from direction)
+pub struct SpotLight{
+    pub pos: Point,
+    pub direction: Vector3,
+    pub color: Color,
+    pub intensity: f32,
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+}
+
+impl SpotLight{
+    // 1 inside the inner cone, 0 outside the outer cone and smooth in between
+    pub fn falloff(&self, hit_point: &Point) -> f32 {
+        let cos_angle = (*hit_point - self.pos).normalize().dot(&self.direction.normalize()) as f32;
+        let cos_inner = self.inner_angle.to_radians().cos();
+        let cos_outer = self.outer_angle.to_radians().cos();
+
+        if cos_angle >= cos_inner {
+            return 1.0;
+        }
+        if cos_angle <= cos_outer {
+            return 0.0;
+        }
+        let t = (cos_angle - cos_outer) / (cos_inner - cos_outer);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
 pub enum Light{
     Directional(DirectionalLight),
     Point(PointLight),
+    Spot(SpotLight),
+    Area(AreaLight),
+}
+
+// One shadow ray worth of light arriving at a hit point
+pub struct LightSample{
+    pub direction: Vector3,
+    pub distance: f64,
+    pub intensity: f32,
 }
 
 impl Light{
@@ -125,6 +201,8 @@ impl Light{
         match *self {
             Light::Directional(ref d) => &d.color,
             Light::Point(ref p) => &p.color,
+            Light::Spot(ref s) => &s.color,
+            Light::Area(ref a) => &a.color,
         }
     }
 
@@ -135,6 +213,14 @@ impl Light{
                 let r2 = (p.pos - *hit_point).norm() as f32;
                 p.intensity / (4.0 * ::std::f32::consts::PI *r2)
             }
+            Light::Spot(ref s) => {
+                let r2 = (s.pos - *hit_point).norm() as f32;
+                s.falloff(hit_point) * s.intensity / (4.0 * ::std::f32::consts::PI *r2)
+            }
+            Light::Area(ref a) => {
+                let r2 = (a.shape.center() - *hit_point).norm() as f32;
+                a.intensity / (4.0 * ::std::f32::consts::PI *r2)
+            }
             
         }
     }
@@ -144,13 +230,29 @@ impl Light{
         match *self {
             Light::Directional(ref d) => (zero-d.direction).normalize(),
             Light::Point(ref p) => (p.pos - *hit_point).normalize(),
+            Light::Spot(ref s) => (s.pos - *hit_point).normalize(),
+            Light::Area(ref a) => (a.shape.center() - *hit_point).normalize(),
         }
     }
 
     pub fn distance(&self, hit_point: &Point) -> f64 {
         match *self {
-            Light::Directional(_) => ::std::f64::INFINITY,
+            Light::Directional(_) => f64::INFINITY,
             Light::Point(ref p) => (p.pos - *hit_point).length(),
+            Light::Spot(ref s) => (s.pos - *hit_point).length(),
+            Light::Area(ref a) => (a.shape.center() - *hit_point).length(),
+        }
+    }
+
+    // Where to send shadow rays from hit_point, area lights give several
+    pub fn sample(&self, hit_point: &Point, rng: &mut Rng) -> Vec<LightSample> {
+        match *self {
+            Light::Area(ref a) => a.sample(hit_point, rng),
+            _ => vec![LightSample{
+                direction: self.direction(hit_point),
+                distance: self.distance(hit_point),
+                intensity: self.intensity(hit_point),
+            }],
         }
     }
 
@@ -163,10 +265,42 @@ pub struct Texture_thing{
     pub y: f32,
 }
 
+// How the 8-bit values of an image are stored
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColorSpace {
+    Srgb,    // Colors, like every normal picture
+    Linear,  // Plain numbers that shouldn't be touched, like normal maps
+}
+
+// Image converted to linear colors once when it's loaded
+#[derive(Debug)]
+pub struct LinearImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color>,
+}
+
+impl LinearImage {
+    pub fn from_image(img: &DynamicImage, color_space: ColorSpace) -> LinearImage {
+        LinearImage {
+            width: img.width(),
+            height: img.height(),
+            pixels: img.pixels().map(|(_, _, p)| match color_space {
+                ColorSpace::Srgb => Color::from_srgba(p),
+                ColorSpace::Linear => Color::from_rgba(p),
+            }).collect(),
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> &Color {
+        &self.pixels[(y * self.width + x) as usize]
+    }
+}
+
 #[derive(Debug)]
 pub enum Texture {
     color(Color),
-    image(DynamicImage),
+    image(LinearImage),
 }
 
 impl Texture {
@@ -175,10 +309,10 @@ impl Texture {
             Texture::color(ref c) => c.clone(),
             Texture::image(ref img) => {
 
-                let img_x = wrap(coords.x, img.width());
-                let img_y = wrap(coords.y, img.height());
+                let img_x = wrap(coords.x, img.width);
+                let img_y = wrap(coords.y, img.height);
 
-                Color::from_rgba(img.get_pixel(img_x, img_y))
+                img.get(img_x, img_y).clone()
 
             }
         }
@@ -203,30 +337,44 @@ pub struct Color{
     pub blue: f32,
 }
 impl Color{
+    pub fn black() -> Color {
+        Color{ red: 0.0, green: 0.0, blue: 0.0 }
+    }
+
+    // sRGB encoded, values outside [0, 1] are clamped
     pub fn to_rgba(&self) -> Rgba<u8> {
+        let encode = |linear: f32| (encode_srgb(linear.clamp(0.0, 1.0)) * 255.0).round() as u8;
         Rgba::from_channels(
-            (encode_gamma(self.red) * 255.0) as u8,
-            (encode_gamma(self.green) * 255.0) as u8,
-            (encode_gamma(self.blue) * 255.0) as u8,
+            encode(self.red),
+            encode(self.green),
+            encode(self.blue),
             255,
         )
     }
 
-    pub fn from_rgba(rgba: Rgba<u8>) -> Color {        
-        
+    // For 8-bit colors that are sRGB encoded, which is most of them
+    pub fn from_srgba(rgba: Rgba<u8>) -> Color {
+        Color{
+            red: decode_srgb((rgba[0] as f32) / 255.0),
+            green: decode_srgb((rgba[1] as f32) / 255.0),
+            blue: decode_srgb((rgba[2] as f32) / 255.0),
+        }
+    }
+
+    // Keeps the values as they are
+    pub fn from_rgba(rgba: Rgba<u8>) -> Color {
         Color{
             red: (rgba[0] as f32) / 255.0,
             green: (rgba[1] as f32) / 255.0,
             blue: (rgba[2] as f32) / 255.0,
         }
-
     }
 
     pub fn clamp(&self) -> Color {
         Color {
-            red: self.red.min(1.0).max(0.0),
-            blue: self.blue.min(1.0).max(0.0),
-            green: self.green.min(1.0).max(0.0),
+            red: self.red.clamp(0.0, 1.0),
+            blue: self.blue.clamp(0.0, 1.0),
+            green: self.green.clamp(0.0, 1.0),
         }
     }
 }
@@ -283,6 +431,29 @@ pub struct Material{
 
     pub surface: surface_type,
 
+    // Light given off by the surface itself, black for anything that doesn't glow
+    pub emission: Color,
+    pub emission_strength: f32,
+    // Above 0 other surfaces send this many shadow rays towards it, like an area light
+    pub emission_samples: u32,
+}
+
+impl Material{
+    // Plain matte surface that doesn't glow
+    pub fn diffuse(color: Color) -> Material {
+        Material {
+            color: Texture::color(color),
+            albedo: 1.0,
+            surface: surface_type::Diffuse,
+            emission: Color::black(),
+            emission_strength: 0.0,
+            emission_samples: 0,
+        }
+    }
+
+    pub fn emitted(&self) -> Color {
+        self.emission.clone() * self.emission_strength
+    }
 }
 
 
@@ -306,6 +477,14 @@ pub struct Plane{
 pub enum Element{
     Sphere(Sphere),
     Plane(Plane),
+    Triangle(Triangle),
+    Box(Cuboid),
+    Cylinder(Cylinder),
+    Cone(Cone),
+    Disk(Disk),
+    Torus(Torus),
+    Csg(Csg),
+    Sdf(Sdf),
 }
 
 impl Element {
@@ -313,11 +492,56 @@ impl Element {
         match *self {
             Element::Sphere(ref s) => &s.material,
             Element::Plane(ref p) => &p.material,
+            Element::Triangle(ref t) => t.material(),
+            Element::Box(ref b) => &b.material,
+            Element::Cylinder(ref c) => &c.material,
+            Element::Cone(ref c) => &c.material,
+            Element::Disk(ref d) => &d.material,
+            Element::Torus(ref t) => &t.material,
+            Element::Csg(ref c) => c.material(),
+            Element::Sdf(ref s) => &s.material,
+        }
+    }
+
+    // Normal turned towards where the ray came from for shapes that are only a surface
+    pub fn facing_normal(&self, hit_point: &Point, direction: &Vector3) -> Vector3 {
+        match *self {
+            Element::Triangle(ref t) => t.facing_normal(hit_point, direction),
+            Element::Disk(ref d) => d.facing_normal(hit_point, direction),
+            _ => self.surface_normal(hit_point),
+        }
+    }
+
+    // Has an inside, so its intervals can be used in a csg
+    pub fn is_solid(&self) -> bool {
+        !matches!(*self, Element::Triangle(_) | Element::Disk(_))
+    }
+
+    // None for elements that go on forever
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        match *self {
+            Element::Sphere(ref s) => {
+                let radius = Vector3::one_to_all(s.radius);
+                Some(Aabb {
+                    min: s.center - radius,
+                    max: s.center + radius,
+                })
+            },
+            Element::Plane(_) => None,
+            Element::Triangle(ref t) => Some(t.bounding_box()),
+            Element::Box(ref b) => Some(b.bounding_box()),
+            Element::Cylinder(ref c) => Some(c.bounding_box()),
+            Element::Cone(ref c) => Some(c.bounding_box()),
+            Element::Disk(ref d) => Some(d.bounding_box()),
+            Element::Torus(ref t) => Some(t.bounding_box()),
+            Element::Csg(ref c) => c.bounding_box(),
+            Element::Sdf(ref s) => s.bounding_box(),
         }
     }
 }
 
 
+#[allow(clippy::manual_non_exhaustive)]
 pub struct Intersection<'a> {
     pub distance: f64,
     pub element: &'a Element,
@@ -325,15 +549,15 @@ pub struct Intersection<'a> {
     _secret: (),
 }
 impl<'a> Intersection<'a> {
-    pub fn new<'b>(distance: f64, element: &'b Element) -> Intersection<'b> {
+    pub fn new<'b>(distance: f64, element: &'b Element) -> Result<Intersection<'b>, RenderError> {
         if !distance.is_finite() {
-            panic!("Intersection must have a finite distance.");
+            return Err(RenderError::InvalidGeometry(format!("intersection at non-finite distance {}", distance)));
         }
-        Intersection {
-            distance: distance,
-            element: element,
+        Ok(Intersection {
+            distance,
+            element,
             _secret: (),
-        }
+        })
     }
 }
 
@@ -343,6 +567,19 @@ pub trait Intersectable {
     fn surface_normal(&self, hit_point: &Point) -> Vector3;
 
     fn texture_coords(&self, hit_point: &Point) -> Texture_thing;
+
+    // Every stretch of the whole line the ray lies on that is inside the element, sorted and
+    // including the parts behind the origin. Left alone it only gives the surface hit in front
+    // as an empty stretch, which is all flat things have. Those can't be part of a csg.
+    fn intervals(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        self.intersect(ray).map(|t| vec![(t, t)]).unwrap_or_default()
+    }
+
+    // Same as intervals when only the part between from and to matters, which lets shapes that
+    // have to search for their surface stop there. Stretches may still reach past both ends.
+    fn intervals_within(&self, ray: &Ray, _from: f64, _to: f64) -> Vec<(f64, f64)> {
+        self.intervals(ray)
+    }
 }
 
 impl Intersectable  for Element {
@@ -350,20 +587,67 @@ impl Intersectable  for Element {
         match *self {
             Element::Sphere(ref s) => s.intersect(ray),
             Element::Plane(ref p) => p.intersect(ray),
+            Element::Triangle(ref t) => t.intersect(ray),
+            Element::Box(ref b) => b.intersect(ray),
+            Element::Cylinder(ref c) => c.intersect(ray),
+            Element::Cone(ref c) => c.intersect(ray),
+            Element::Disk(ref d) => d.intersect(ray),
+            Element::Torus(ref t) => t.intersect(ray),
+            Element::Csg(ref c) => c.intersect(ray),
+            Element::Sdf(ref s) => s.intersect(ray),
         }
     }
 
     fn surface_normal(&self, hit_point: &Point) -> Vector3{
         match *self {
-            Element::Sphere(ref s) => s.surface_normal(&hit_point),
-            Element::Plane(ref p) => p.surface_normal(&hit_point),
+            Element::Sphere(ref s) => s.surface_normal(hit_point),
+            Element::Plane(ref p) => p.surface_normal(hit_point),
+            Element::Triangle(ref t) => t.surface_normal(hit_point),
+            Element::Box(ref b) => b.surface_normal(hit_point),
+            Element::Cylinder(ref c) => c.surface_normal(hit_point),
+            Element::Cone(ref c) => c.surface_normal(hit_point),
+            Element::Disk(ref d) => d.surface_normal(hit_point),
+            Element::Torus(ref t) => t.surface_normal(hit_point),
+            Element::Csg(ref c) => c.surface_normal(hit_point),
+            Element::Sdf(ref s) => s.surface_normal(hit_point),
         }
     }
 
     fn texture_coords(&self, hit_point: &Point) -> Texture_thing{
         match *self {
-            Element::Sphere(ref s) => s.texture_coords(&hit_point),
-            Element::Plane(ref p) => p.texture_coords(&hit_point),
+            Element::Sphere(ref s) => s.texture_coords(hit_point),
+            Element::Plane(ref p) => p.texture_coords(hit_point),
+            Element::Triangle(ref t) => t.texture_coords(hit_point),
+            Element::Box(ref b) => b.texture_coords(hit_point),
+            Element::Cylinder(ref c) => c.texture_coords(hit_point),
+            Element::Cone(ref c) => c.texture_coords(hit_point),
+            Element::Disk(ref d) => d.texture_coords(hit_point),
+            Element::Torus(ref t) => t.texture_coords(hit_point),
+            Element::Csg(ref c) => c.texture_coords(hit_point),
+            Element::Sdf(ref s) => s.texture_coords(hit_point),
+        }
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        match *self {
+            Element::Sphere(ref s) => s.intervals(ray),
+            Element::Plane(ref p) => p.intervals(ray),
+            Element::Triangle(ref t) => t.intervals(ray),
+            Element::Box(ref b) => b.intervals(ray),
+            Element::Cylinder(ref c) => c.intervals(ray),
+            Element::Cone(ref c) => c.intervals(ray),
+            Element::Disk(ref d) => d.intervals(ray),
+            Element::Torus(ref t) => t.intervals(ray),
+            Element::Csg(ref c) => c.intervals(ray),
+            Element::Sdf(ref s) => s.intervals(ray),
+        }
+    }
+
+    fn intervals_within(&self, ray: &Ray, from: f64, to: f64) -> Vec<(f64, f64)> {
+        match *self {
+            Element::Csg(ref c) => c.intervals_within(ray, from, to),
+            Element::Sdf(ref s) => s.intervals_within(ray, from, to),
+            _ => self.intervals(ray),
         }
     }
 }
@@ -375,7 +659,7 @@ impl Intersectable for Plane {
 
         if denom > 1e-6 {
             let v = self.center - ray.origin;
-            let distance = v.dot(&normal) / denom;
+            let distance = v.dot(normal) / denom;
             if distance >= 0.0 {
                 return Some(distance);
             }
@@ -414,6 +698,22 @@ impl Intersectable for Plane {
         }
         
     }
+
+    // Everything on the side normal points to is inside
+    fn intervals(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        let denom = self.normal.dot(&ray.direction);
+        let depth = (ray.origin - self.center).dot(&self.normal);
+
+        if denom == 0.0 {
+            return if depth > 0.0 { vec![(f64::NEG_INFINITY, f64::INFINITY)] } else { Vec::new() };
+        }
+        let distance = -depth / denom;
+        if denom > 0.0 {
+            vec![(distance, f64::INFINITY)]
+        } else {
+            vec![(f64::NEG_INFINITY, distance)]
+        }
+    }
 }
 
 impl Intersectable for Sphere {
@@ -437,7 +737,7 @@ impl Intersectable for Sphere {
         let ray_thicc = ray_direction + thicc;
 
         if thicc_ray < 0.0 && ray_thicc < 0.0 {
-            return None;
+            None
         } else if thicc_ray < 0.0 {
             Some(ray_thicc)
         } else if ray_thicc < 0.0 {
@@ -462,97 +762,318 @@ impl Intersectable for Sphere {
         }
 
     }
+
+    fn intervals(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        let origin_center: Vector3 = self.center - ray.origin;
+        let ray_direction = origin_center.dot(&ray.direction);
+        let center_ray = origin_center.dot(&origin_center) - (ray_direction * ray_direction);
+        let radius = self.radius * self.radius;
+
+        if center_ray > radius {
+            return Vec::new();
+        }
+        let thicc = (radius - center_ray).sqrt();
+        vec![(ray_direction - thicc, ray_direction + thicc)]
+    }
 }
 
 pub struct Scene{
     pub width: u32,
     pub height: u32,
-    pub fov: f64,
+    pub camera: Camera,
 
-    pub elements: Vec<Element>,
+    // Private so the bvh can't go out of date, change them through elements_mut
+    elements: Vec<Element>,
 
     pub lights: Vec<Light>,
     pub bias: f64,
 
     pub max_rec: u32,
+
+    pub threads: usize,  // 0 uses every available core
+
+    pub samples: u32,  // rays per pixel
+    pub sampling: Sampling,
+    pub integrator: Integrator,
+
+    pub tone_map: ToneMap,
+    pub exposure: f32,  // stops, applied before tone mapping
+
+    pub background: Background,
+    pub environment_samples: u32,  // rays per hit point lighting diffuse surfaces from the background, 0 turns it off
+
+    // Built from elements on the first trace, elements_mut resets them
+    bvh: OnceLock<Bvh>,
+    emitters: OnceLock<Vec<usize>>,
 }
 impl Scene {
-    pub fn trace (&self, ray: &Ray) -> Option<Intersection> {
-        self.elements
-            .iter()
-            .filter_map(|e| e.intersect(ray).map(|d| Intersection::new(d, e)))
-            .min_by(|i1, i2| i1.distance.partial_cmp(&i2.distance).unwrap())
+    pub fn trace (&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.bvh()
+            .trace(ray, &self.elements)
+            .and_then(|(d, i)| Intersection::new(d, &self.elements[i]).ok())
     }
-}
 
-fn diffuse_shading(scene: &Scene, ray: &Ray, intersection: &Intersection, hit_point: Point, surface_normal: Vector3) -> Color{
+    // Catches geometry that would otherwise give NaN rays or divide by zero while rendering
+    pub fn validate(&self) -> Result<(), RenderError> {
+        let invalid = |message: String| Err(RenderError::InvalidGeometry(message));
 
-    let texture_coords = intersection.element.texture_coords(&hit_point);
-    let zero: Vector3 = Vector3::zero();
+        if self.width == 0 || self.height == 0 {
+            return invalid(format!("image size {}x{} is empty", self.width, self.height));
+        }
 
-    
-    let mut combined_color = Color{
-        red: 0.0,
-        green: 0.0,
-        blue: 0.0,
-    };
+        let camera = &self.camera;
+        if !camera.eye.is_finite() || !camera.target.is_finite() || !camera.up.is_finite() {
+            return invalid("camera position is not finite".to_string());
+        }
+        if !(camera.fov > 0.0 && camera.fov < 180.0) {
+            return invalid(format!("camera fov {} is not between 0 and 180", camera.fov));
+        }
+        let forward = camera.target - camera.eye;
+        if forward.length() == 0.0 || forward.cross(&camera.up).length() == 0.0 {
+            return invalid("camera looks at itself or along its up vector".to_string());
+        }
 
+        for (i, element) in self.elements.iter().enumerate() {
+            // The parts of a csg are checked like the element itself
+            let mut parts = vec![element];
+            while let Some(element) = parts.pop() {
+                match *element {
+                    Element::Sphere(ref s) => {
+                        if !(s.center.is_finite() && s.radius > 0.0 && s.radius.is_finite()) {
+                            return invalid(format!("element {} is a sphere with radius {}", i, s.radius));
+                        }
+                    },
+                    Element::Plane(ref p) => {
+                        if !p.center.is_finite() || !p.normal.is_finite() || p.normal.length() == 0.0 {
+                            return invalid(format!("element {} is a plane without a normal", i));
+                        }
+                    },
+                    Element::Triangle(ref t) => {
+                        let face = &t.mesh.faces[t.face];
+                        if face.vertices.iter().any(|&v| !t.mesh.vertices[v].is_finite()) {
+                            return invalid(format!("element {} is a triangle with a non-finite vertex", i));
+                        }
+                    },
+                    Element::Box(ref b) => {
+                        if !b.is_valid() {
+                            return invalid(format!("element {} is a box without any volume", i));
+                        }
+                    },
+                    Element::Cylinder(ref c) => {
+                        if !c.is_valid() {
+                            return invalid(format!("element {} is a cylinder with radius {} and height {}", i, c.radius, c.height));
+                        }
+                    },
+                    Element::Cone(ref c) => {
+                        if !c.is_valid() {
+                            return invalid(format!("element {} is a cone with radii {} and {} and height {}", i, c.radius, c.top_radius, c.height));
+                        }
+                    },
+                    Element::Disk(ref d) => {
+                        if !d.is_valid() {
+                            return invalid(format!("element {} is a disk with radius {} or without a normal", i, d.radius));
+                        }
+                    },
+                    Element::Torus(ref t) => {
+                        if !t.is_valid() {
+                            return invalid(format!("element {} is a torus with radii {} and {}", i, t.major_radius, t.minor_radius));
+                        }
+                    },
+                    Element::Sdf(ref s) => {
+                        if !s.is_valid() {
+                            return invalid(format!("element {} is a distance field with a shape that has no size", i));
+                        }
+                    },
+                    Element::Csg(ref c) => {
+                        if !c.left.is_solid() || !c.right.is_solid() {
+                            return invalid(format!("element {} is a csg with a flat part", i));
+                        }
+                        parts.push(&c.left);
+                        parts.push(&c.right);
+                    },
+                }
+            }
+        }
 
+        for (i, light) in self.lights.iter().enumerate() {
+            match *light {
+                Light::Spot(ref s) => {
+                    if !s.pos.is_finite() || !s.direction.is_finite() || s.direction.length() == 0.0 {
+                        return invalid(format!("light {} is a spot light without a direction", i));
+                    }
+                    if !(s.inner_angle >= 0.0 && s.inner_angle <= s.outer_angle && s.outer_angle <= 180.0) {
+                        return invalid(format!("light {} has cone angles {} and {}", i, s.inner_angle, s.outer_angle));
+                    }
+                },
+                Light::Area(ref a) if !a.shape.is_valid() => {
+                    return invalid(format!("light {} is an area light without any area", i));
+                },
+                _ => {},
+            }
+        }
 
-    for light in &scene.lights{
-        let direction_light = light.direction(&hit_point);
+        Ok(())
+    }
+
+    pub fn elements(&self) -> &[Element] {
+        &self.elements
+    }
+
+    // The bvh and emitters are found again on the next trace
+    pub fn elements_mut(&mut self) -> &mut Vec<Element> {
+        self.bvh = OnceLock::new();
+        self.emitters = OnceLock::new();
+        &mut self.elements
+    }
+
+    pub fn bvh(&self) -> &Bvh {
+        self.bvh.get_or_init(|| Bvh::build(&self.elements))
+    }
+
+    // Indices of the elements that are sampled as lights
+    pub fn emitters(&self) -> &[usize] {
+        self.emitters.get_or_init(|| {
+            (0..self.elements.len())
+                .filter(|&i| {
+                    let material = self.elements[i].material();
+                    can_sample(&self.elements[i]) && material.emission_samples > 0 && material.emission_strength > 0.0
+                })
+                .collect()
+        })
+    }
+
+    pub fn thread_count(&self) -> usize {
+        if self.threads > 0 {
+            self.threads
+        } else {
+            thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+        }
+    }
+}
 
+// Average light reaching hit_point from the samples, hitting `source` itself doesn't count as a shadow
+fn light_power(scene: &Scene, samples: &[LightSample], hit_point: Point, surface_normal: Vector3, source: Option<&Element>) -> f32 {
+    let mut light_power = 0.0;
 
+    for sample in samples {
         let shadow_ray = Ray{
             origin: hit_point + (surface_normal * scene.bias),
-            direction: direction_light,
+            direction: sample.direction,
+        };
+
+        let is_not_shadow = match scene.trace(&shadow_ray) {
+            None => true,
+            Some(ref s) => s.distance > sample.distance || source.is_some_and(|e| std::ptr::eq(e, s.element)),
+        };
+
+        if is_not_shadow {
+            light_power += (surface_normal.dot(&sample.direction) as f32).max(0.0) * sample.intensity;
+        }
+    }
+
+    // Averaging the shadow rays is what makes the penumbra
+    if samples.is_empty() { 0.0 } else { light_power / samples.len() as f32 }
+}
+
+// Background light reaching hit_point, the cosine weighting of the samples cancels the one in the
+// rendering equation so this is just the average of what the unblocked rays see
+fn environment_light(scene: &Scene, hit_point: Point, surface_normal: Vector3, rng: &mut Rng) -> Color {
+    let mut light = Color::black();
+
+    for (s, t) in pixel_offsets(Sampling::Jittered, scene.environment_samples, rng) {
+        let ray = Ray{
+            origin: hit_point + (surface_normal * scene.bias),
+            direction: cosine_hemisphere(&surface_normal, s, t),
         };
+        if scene.trace(&ray).is_none() {
+            light = light + scene.background.sample(&ray.direction);
+        }
+    }
+
+    light * (1.0 / scene.environment_samples as f32)
+}
 
+// Light arriving at hit_point straight from the lights and the glowing elements, except from element itself
+fn direct_light(scene: &Scene, element: &Element, hit_point: Point, surface_normal: Vector3, rng: &mut Rng) -> Color {
+    let mut combined_color = Color::black();
 
-        let shadow_intersection = scene.trace(&shadow_ray);
+    for light in &scene.lights{
+        let samples = light.sample(&hit_point, rng);
+        let light_power = light_power(scene, &samples, hit_point, surface_normal, None);
 
-        let mut distance:f64 = 0.0;
+        combined_color = combined_color + light.color().clone() * light_power;
+    }
 
-        if !shadow_intersection.is_none(){
-            distance = shadow_intersection.as_ref().unwrap().distance;
+    for &i in scene.emitters() {
+        let emitter = &scene.elements[i];
+        if std::ptr::eq(emitter, element) {
+            continue;
         }
-        
-        let is_not_shadow = shadow_intersection.is_none() || distance > light.distance(&hit_point);
 
-        let new_light_intensity = if is_not_shadow {light.intensity(&hit_point)} else{0.0};
+        let samples = sample_emitter(emitter, emitter.material().emission_samples, &hit_point, rng);
+        let light_power = light_power(scene, &samples, hit_point, surface_normal, Some(emitter));
 
-        let material = intersection.element.material();
+        combined_color = combined_color + emitter.material().emission.clone() * light_power;
+    }
+
+    combined_color
+}
+
+fn diffuse_shading(scene: &Scene, intersection: &Intersection, hit_point: Point, surface_normal: Vector3) -> Color{
 
-        let light_power = (surface_normal.dot(&direction_light) as f32).max(0.0) * new_light_intensity;
+    let texture_coords = intersection.element.texture_coords(&hit_point);
 
-        let reflected_light = material.albedo / std::f32::consts::PI;
+    let mut rng = Rng::for_point(&hit_point);
 
-        let color = light.color().clone() * light_power * reflected_light;
+    let material = intersection.element.material();
+    let reflected_light = material.albedo / std::f32::consts::PI;
 
-        combined_color = combined_color + (material.color.get_color(&texture_coords) * color);
+    let mut combined_color = direct_light(scene, intersection.element, hit_point, surface_normal, &mut rng) * reflected_light;
 
+    if scene.environment_samples > 0 {
+        combined_color = combined_color + environment_light(scene, hit_point, surface_normal, &mut rng) * material.albedo;
     }
     
-    combined_color.clamp()
+    material.color.get_color(&texture_coords) * combined_color
+
+}
+
+// Fraction of light that is reflected off a transparent surface, 1.0 on total internal reflection
+fn fresnel(incident: Vector3, normal: Vector3, index: f32) -> f32 {
+    let cos_incident = incident.normalize().dot(&normal);
 
+    let (eta_i, eta_t) = if cos_incident < 0.0 { (1.0, index as f64) } else { (index as f64, 1.0) };
+
+    let sin_transmitted = eta_i / eta_t * (1.0 - cos_incident * cos_incident).max(0.0).sqrt();
+
+    if sin_transmitted >= 1.0 {
+        return 1.0;
+    }
+
+    let cos_transmitted = (1.0 - sin_transmitted * sin_transmitted).max(0.0).sqrt();
+    let cos_incident = cos_incident.abs();
+
+    let r_s = (eta_t * cos_incident - eta_i * cos_transmitted) / (eta_t * cos_incident + eta_i * cos_transmitted);
+    let r_p = (eta_i * cos_incident - eta_t * cos_transmitted) / (eta_i * cos_incident + eta_t * cos_transmitted);
+
+    ((r_s * r_s + r_p * r_p) / 2.0) as f32
 }
 
 fn get_color(scene: &Scene, ray: &Ray, intersection: &Intersection, depth: u32) -> Color{
     let hit_point = ray.origin + (ray.direction * intersection.distance);
-    let surface_normal = intersection.element.surface_normal(&hit_point);
+    let surface_normal = intersection.element.facing_normal(&hit_point, &ray.direction);
     let texture_coords = intersection.element.texture_coords(&hit_point);
-    let zero: Vector3 = Vector3::zero();
 
     
-    //let mut combined_color = diffuse_shading(scene, ray, intersection, hit_point, surface_normal);
+    //let mut combined_color = diffuse_shading(scene, intersection, hit_point, surface_normal);
 
     let material= intersection.element.material();
 
-    match material.surface {
-        surface_type::Diffuse => diffuse_shading(scene, ray, intersection, hit_point, surface_normal),
+    let color = match material.surface {
+        surface_type::Diffuse => diffuse_shading(scene, intersection, hit_point, surface_normal),
         surface_type::Reflective{reflectivity} => {
 
-            let mut ref_color = diffuse_shading(scene, ray, intersection, hit_point, surface_normal);
+            let mut ref_color = diffuse_shading(scene, intersection, hit_point, surface_normal);
 
             let reflection = Ray::create_reflection(surface_normal, ray.direction, hit_point, scene.bias);
 
@@ -561,33 +1082,37 @@ fn get_color(scene: &Scene, ray: &Ray, intersection: &Intersection, depth: u32)
             ref_color
         },
         surface_type::Transparent{index, transparency} => {
-            
-            let mut trans_color = Color{
-                red: 0.0,
-                green: 0.0,
-                blue: 0.0,
-            };
 
-            let surface_color = material.color.get_color(&intersection.element.texture_coords(&hit_point));
+            let surface_color = material.color.get_color(&texture_coords);
 
-            let transmission_ray = Ray::create_transmission(surface_normal, ray.direction, hit_point, scene.bias, index).unwrap_or(Ray{
-                origin: hit_point,
-                direction: ray.direction,
-            });
+            // Reflections from the inside have to start on the inside
+            let zero: Vector3 = Vector3::zero();
+            let reflect_normal = if ray.direction.dot(&surface_normal) < 0.0 { surface_normal } else { zero - surface_normal };
 
-            trans_color = raycast(scene, &transmission_ray, depth+1);
+            let reflection = Ray::create_reflection(reflect_normal, ray.direction, hit_point, scene.bias);
 
-            let reflection = Ray::create_reflection(surface_normal, ray.direction, hit_point, scene.bias);
+            let ref_color = raycast(scene, &reflection, depth + 1);
 
-            let ref_color = (raycast(scene, &reflection, depth + 1));
+            // Which way the surface is wound tells the inside from the outside
+            let normal = intersection.element.surface_normal(&hit_point);
+            let kr = fresnel(ray.direction, normal, index);
 
-            let mut color = ref_color + trans_color;
+            let mut color = match Ray::create_transmission(normal, ray.direction, hit_point, scene.bias, index) {
+                Some(transmission_ray) if kr < 1.0 => {
+                    let trans_color = raycast(scene, &transmission_ray, depth+1);
+                    ref_color * kr + trans_color * (1.0 - kr)
+                },
+                // Total internal reflection, everything is reflected
+                _ => ref_color,
+            };
 
             color = color * transparency * surface_color;
             
             color
         },
-    }
+    };
+
+    color + material.emitted()
 }
 
 pub fn raycast(scene: &Scene, ray: &Ray, depth: u32) -> Color{
@@ -602,35 +1127,152 @@ pub fn raycast(scene: &Scene, ray: &Ray, depth: u32) -> Color{
         return none
     }
 
-    let intersection = scene.trace(&ray);
+    let intersection = scene.trace(ray);
 
-    intersection.map(|i| get_color(scene, &ray, &i, depth))
-        .unwrap_or(none)
+    intersection.map(|i| get_color(scene, ray, &i, depth))
+        .unwrap_or_else(|| scene.background.sample(&ray.direction))
 }
 
-pub fn render_scene(scene: &Scene) -> DynamicImage {
+const TILE_SIZE: u32 = 32;
 
-    
-    let none = Rgba::from_channels(0,0,0,0);
-    let mut output = DynamicImage::new_rgb8(scene.width, scene.height);
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+fn create_tiles(width: u32, height: u32) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+
+    for y in (0..height).step_by(TILE_SIZE as usize) {
+        for x in (0..width).step_by(TILE_SIZE as usize) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: TILE_SIZE.min(width - x),
+                height: TILE_SIZE.min(height - y),
+            });
+        }
+    }
+    tiles
+}
+
+// Average of all samples in linear color space
+fn render_pixel(scene: &Scene, x: u32, y: u32) -> Color {
+    let mut rng = Rng::for_pixel(x, y);
+    let offsets = sampling::pixel_offsets(scene.sampling, scene.samples, &mut rng);
 
-    for x in 0..scene.width {
-        for y in 0..scene.height {
+    let mut color = Color{
+        red: 0.0,
+        green: 0.0,
+        blue: 0.0,
+    };
+
+    for &(offset_x, offset_y) in &offsets {
+        let ray = Ray::create_prime_sample(x, y, offset_x, offset_y, scene);
+        color = color + match scene.integrator {
+            Integrator::Whitted => raycast(scene, &ray, 0),
+            Integrator::PathTracing => trace_path(scene, ray, &mut rng),
+        };
+    }
+
+    color * (1.0 / offsets.len() as f32)
+}
+
+// Pixels of the tile, row by row
+fn render_tile(scene: &Scene, tile: &Tile) -> Vec<Color> {
+    let mut pixels = Vec::with_capacity((tile.width * tile.height) as usize);
+
+    for y in tile.y..tile.y + tile.height {
+        for x in tile.x..tile.x + tile.width {
+
+            pixels.push(render_pixel(scene, x, y));
+        }
+    }
+    pixels
+}
+
+pub fn render_scene(scene: &Scene) -> Result<DynamicImage, RenderError> {
+    render_scene_with(scene, &CancelToken::new(), |_| {})
+}
+
+// Tone mapped with the scene's tone_map and exposure
+pub fn render_scene_with<F: FnMut(&Progress)>(scene: &Scene, cancel: &CancelToken, progress: F) -> Result<DynamicImage, RenderError> {
+    render_hdr_with(scene, cancel, progress).map(|framebuffer| framebuffer.to_image(scene.tone_map, scene.exposure))
+}
+
+pub fn render_hdr(scene: &Scene) -> Result<Framebuffer, RenderError> {
+    render_hdr_with(scene, &CancelToken::new(), |_| {})
+}
+
+// The linear colors before tone mapping, progress is called on the calling thread after every finished tile
+pub fn render_hdr_with<F: FnMut(&Progress)>(scene: &Scene, cancel: &CancelToken, mut progress: F) -> Result<Framebuffer, RenderError> {
+
+    scene.validate()?;
+
+    let start = Instant::now();
+
+    let mut output = Framebuffer::new(scene.width, scene.height);
+
+    let tiles = create_tiles(scene.width, scene.height);
+    let next_tile = AtomicUsize::new(0);
+    let threads = scene.thread_count().min(tiles.len()).max(1);
+
+    let (sender, receiver) = mpsc::channel();
+    let mut tiles_done = 0;
+
+    thread::scope(|s| {
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let tiles = &tiles;
+            let next_tile = &next_tile;
+
+            s.spawn(move || loop {
+                if cancel.is_cancelled() {
+                    break;
+                }
+                let i = next_tile.fetch_add(1, Ordering::Relaxed);
+                if i >= tiles.len() {
+                    break;
+                }
+                if sender.send((i, render_tile(scene, &tiles[i]))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        for (i, pixels) in receiver {
+            let tile = &tiles[i];
+            for (n, pixel) in pixels.into_iter().enumerate() {
+                let n = n as u32;
+                output.set(tile.x + n % tile.width, tile.y + n / tile.width, pixel);
+            }
 
-            let ray = Ray::create_prime(x,y,scene);
-            output.put_pixel(x, y, raycast(scene, &ray, 0).to_rgba());
+            tiles_done += 1;
+            progress(&Progress {
+                tiles_done,
+                tiles_total: tiles.len(),
+                elapsed: start.elapsed(),
+            });
         }
+    });
+
+    if tiles_done < tiles.len() {
+        return Err(RenderError::Cancelled);
     }
-    output
+
+    Ok(output)
 }
 
 #[test]
-fn test_can_renderScene_scene(){
+fn test_can_render_scene(){
 
     let scene = Scene{
         width: 800,
         height: 600,
-        fov: 90.0, 
+        camera: Camera::origin(90.0),
         elements: vec![Element::Sphere(Sphere{  //Green ball
             center: Point {
                 x:0.0,
@@ -640,13 +1282,17 @@ fn test_can_renderScene_scene(){
             },
             radius: 1.0,
             material: Material{
-                color: Color{
+                color: Texture::color(Color{
                     red: 0.2,
                     green: 0.8,
                     blue: 0.2,
     
-                },
+                }),
                 albedo: 1.0,
+                surface: surface_type::Diffuse,
+                emission: Color::black(),
+                emission_strength: 0.0,
+                emission_samples: 0,
             }
 
         }), Element::Sphere(Sphere{   //Small Yellow ball
@@ -658,12 +1304,16 @@ fn test_can_renderScene_scene(){
             },
             radius: 0.5,
             material: Material{
-                color: Color{
+                color: Texture::color(Color{
                     red: 0.8,
                     green: 0.8,
                     blue: 0.2,
-                },
+                }),
                 albedo: 1.0,
+                surface: surface_type::Diffuse,
+                emission: Color::black(),
+                emission_strength: 0.0,
+                emission_samples: 0,
             }
 
         }),Element::Sphere(Sphere{  //Red ball
@@ -675,13 +1325,17 @@ fn test_can_renderScene_scene(){
             },
             radius: 2.0,
             material: Material{
-                color: Color{
+                color: Texture::color(Color{
                     red: 0.8,
                     green: 0.2,
                     blue: 0.2,
     
-                },
+                }),
                 albedo: 1.0,
+                surface: surface_type::Diffuse,
+                emission: Color::black(),
+                emission_strength: 0.0,
+                emission_samples: 0,
             }
 
         }),Element::Plane(Plane{     //Plane
@@ -697,13 +1351,17 @@ fn test_can_renderScene_scene(){
                 z: 0.0,
             },
             material: Material{
-                color: Color{
+                color: Texture::color(Color{
                     red: 0.2,
                     green: 0.2,
                     blue: 0.2,
     
-                },
+                }),
                 albedo: 1.0,
+                surface: surface_type::Diffuse,
+                emission: Color::black(),
+                emission_strength: 0.0,
+                emission_samples: 0,
             }
 
         })],
@@ -745,102 +1403,42 @@ fn test_can_renderScene_scene(){
             intensity: 10.0,
         })],
         bias:0.1,
+        max_rec: 10,
+        threads: 0,
+        samples: 1,
+        sampling: Sampling::Grid,
+        integrator: Integrator::Whitted,
+        tone_map: ToneMap::Clamp,
+        exposure: 0.0,
+        background: Background::black(),
+        environment_samples: 0,
+        bvh: OnceLock::new(),
+        emitters: OnceLock::new(),
     };
 
-    let img: DynamicImage = render_scene(&scene);
+    let img: DynamicImage = render_scene(&scene).unwrap();
 
 
-    DynamicImage::save(&img, &Path::new("../image.png"));
-    
     assert_eq!(scene.width, img.width());
     assert_eq!(scene.height, img.height());
     assert_eq!(scene.lights.len(), 3);
 
-
-    DynamicImage::save(&img, &Path::new("../image.png"));
-    
-}
-
-#[no_mangle]
-pub extern "system" fn Java_main_hello(_env: JNIEnv, _class: JClass){
-    println!("Hello world");
+    // Something in the scene got lit
+    assert!(img.to_rgb8().pixels().any(|p| p.0 != [0, 0, 0]));
 }
 
-#[no_mangle]
-pub extern fn Java_main_javaCall(){
-
-    let reader = BufReader::new(File::open("inf.txt").expect("Cannot open inf.txt"));
-
-    let mut element_vec: Vec<Element> = Vec::new();
-
-    let mut light_vec: Vec<Light> = Vec::new();
-
-    for line in reader.lines() {
-
-        let words:Vec<String> = line.unwrap().split_whitespace().map(|s| s.to_string()).collect();
-
-        println!("{:#?}", words);
+#[test]
+fn test_threaded_render_matches_single_thread(){
 
-        if words[0].eq("sphere") {
-            let new_element: Element = Element::Sphere(Sphere{
-                center: Point {
-                    x: words[1].parse::<f64>().unwrap(),
-                    y: words[2].parse::<f64>().unwrap(),
-                    z: words[3].parse::<f64>().unwrap(),
-                },
-                radius: words[4].parse::<f64>().unwrap(),
-                material: Material {
-                    color: Texture::color(Color{
-                        red: words[5].parse::<f32>().unwrap() / 255.0,
-                        green: words[6].parse::<f32>().unwrap() / 255.0,
-                        blue: words[7].parse::<f32>().unwrap() / 255.0,
-                    }),
-                    albedo: 1.0,
-                    surface: surface_type::Reflective{reflectivity: 0.1},
-                },
-            });
-            element_vec.push(new_element);
-        }else if words[0].eq("light") {
-
-            let new_light: Light = Light::Directional(DirectionalLight{
-                direction: Vector3{
-                    x: words[1].parse::<f64>().unwrap(),
-                    y: words[2].parse::<f64>().unwrap(),
-                    z: words[3].parse::<f64>().unwrap(),
-                },
-                color:Color{
-                    red: words[5].parse::<f32>().unwrap() / 255.0,
-                    green: words[6].parse::<f32>().unwrap() / 255.0,
-                    blue: words[7].parse::<f32>().unwrap() / 255.0,
-                },
-                intensity: words[4].parse::<f32>().unwrap(),
-            });
-            light_vec.push(new_light);
-
-        }
-        
-    }
-
-    let scene2 = Scene{
-        width: 1920,
-        height: 1080,
-        fov: 90.0,
-        elements: element_vec,
-        lights: light_vec,
-        bias:0.1,
-        max_rec: 10, 
-    };
-    
-    let scene = Scene{
-        width: 1920,
-        height: 1080,
-        fov: 90.0, 
-        elements: vec![Element::Sphere(Sphere{  //Green ball
+    let mut scene = Scene{
+        width: 100,
+        height: 70,
+        camera: Camera::origin(90.0),
+        elements: vec![Element::Sphere(Sphere{
             center: Point {
-                x:0.0,
-                y:0.0,
-                z:-5.0,
-
+                x: 0.0,
+                y: 0.0,
+                z: -3.0,
             },
             radius: 1.0,
             material: Material{
@@ -848,402 +1446,321 @@ pub extern fn Java_main_javaCall(){
                     red: 0.2,
                     green: 0.8,
                     blue: 0.2,
-    
                 }),
                 albedo: 1.0,
-                surface: surface_type::Reflective{reflectivity: 0.1},
-            }
-
-        }), Element::Sphere(Sphere{   //Small Yellow ball
-            center: Point {
-                x:2.0,
-                y:1.0,
-                z:-5.0,
-
-            },
-            radius: 0.5,
-            material: Material{
-                color: Texture::color(Color{
-                    red: 0.8,
-                    green: 0.8,
-                    blue: 0.2,
-                }),
-                albedo: 0.28,
-                surface: surface_type::Transparent{index: 1.5, transparency: 0.6},
-            }
-
-        }), Element::Sphere(Sphere{   //Small amog us ball
-            center: Point {
-                x: -1.0,
-                y: -1.0,
-                z: -2.0,
-
-            },
-            radius: 0.75,
-            material: Material{
-                color: Texture::image(image::open("texture/test.png").unwrap()),
-                albedo: 0.85,
-                surface: surface_type::Transparent{index: 1.5, transparency: 1.0},
+                surface: surface_type::Reflective{reflectivity: 0.3},
+                emission: Color::black(),
+                emission_strength: 0.0,
+                emission_samples: 0,
             }
-
-        }), Element::Sphere(Sphere{   //Small amog us ball
-            center: Point {
-                x:-1.0,
-                y:-1.0,
-                z: 0.5,
-
-            },
-            radius: 0.75,
-            material: Material{
-                color: Texture::image(image::open("texture/test.png").unwrap()),
-                albedo: 0.75,
-                surface: surface_type::Reflective{reflectivity: 0.0},
-            }
-
-        }),Element::Sphere(Sphere{  //Red ball
-            center: Point {
-                x:-2.0,
-                y:2.0,
-                z:-5.0,
-
-            },
-            radius: 2.0,
-            material: Material{
-                color: Texture::color(Color{
-                    red: 0.8,
-                    green: 0.2,
-                    blue: 0.2,
-    
-                }),
-                albedo: 0.75,
-                surface: surface_type::Reflective{reflectivity: 1.0},
-            }
-
-        }),Element::Sphere(Sphere{  //Red ball
+        }),Element::Plane(Plane{
             center: Point {
-                x: 0.0,
-                y: -2.0,
-                z:-10.0,
-
-            },
-            radius: 0.5,
-            material: Material{
-                color: Texture::color(Color{
-                    red: 0.8,
-                    green: 0.2,
-                    blue: 0.2,
-    
-                }),
-                albedo: 0.28,
-                surface: surface_type::Reflective{reflectivity: 1.0},
-            }
-
-        }),Element::Plane(Plane{     //Plane
-            center: Point {
-                x: 0.0,
-                y: -2.0,
-                z: -5.0,
-
-            },
-            normal: Vector3{
                 x: 0.0,
                 y: -1.0,
                 z: 0.0,
             },
-            material: Material{
-                //color: Texture::color(Color{
-                    //red:0.8,
-                    //green:0.8,
-                    //blue: 0.8,
-                //}),
-                color: Texture::image(image::open("texture/check.png").unwrap()),
-                albedo: 0.30,
-                surface: surface_type::Reflective{reflectivity: 0.99},
-            }
-
-        }),Element::Plane(Plane{     //Plane
-            center: Point {
-                x: 0.0,
-                y: 0.0,
-                z: 1.0,
-
-            },
             normal: Vector3{
                 x: 0.0,
-                y: 0.1,
-                z: 1.0,
+                y: -1.0,
+                z: 0.0,
             },
             material: Material{
                 color: Texture::color(Color{
-                    red:0.1,
-                    green:0.4,
+                    red: 0.8,
+                    green: 0.8,
                     blue: 0.8,
                 }),
-                albedo: 0.48,
-                surface: surface_type::Reflective{reflectivity: 0.99},
-            }
-
-        }),Element::Plane(Plane{     //Plane
-            center: Point {
-                x: 0.0,
-                y: 0.0,
-                z: -8.0,
-
-            },
-            normal: Vector3{
-                x: 0.0,
-                y: 0.0 ,
-                z: -1.0,
-            },
-            material: Material{
-                color: Texture::color(Color{
-                    red:0.9,
-                    green:0.4,
-                    blue: 0.1,
-                }),
-                albedo: 0.70,
-                surface: surface_type::Reflective{reflectivity: 0.99},
+                albedo: 1.0,
+                surface: surface_type::Diffuse,
+                emission: Color::black(),
+                emission_strength: 0.0,
+                emission_samples: 0,
             }
-
         })],
-        lights: vec![Light::Directional(DirectionalLight{
-            direction: Vector3{
-                x: 0.5,
-                y: -0.25,
-                z: -0.5,
-            },
-            color: Color{
-                red: 0.8,
-                green: 0.8,
-                blue: 0.8,
-            },
-            intensity: 1.0,
-        }),Light::Directional(DirectionalLight{
-            direction: Vector3{
-                x: 0.0,
-                y: 0.0,
-                z: -1.0,
-            },
-            color: Color{
-                red: 0.1,
-                green: 0.8,
-                blue: 0.1,
-            },
-            intensity: 10.0,
-        }),Light::Point(PointLight{
-            pos: Point{
-                x: -2.0,
-                y: 10.0,
-                z:-3.0,
-            },
-            color: Color{
-                red: 0.9,
-                green: 0.2,
-                blue: 0.1,
-            },
-            intensity: 10000.0,
-        }),Light::Point(PointLight{
+        lights: vec![Light::Point(PointLight{
             pos: Point{
-                x: 0.0,
-                y: 0.0,
-                z:-1.0,
+                x: 1.0,
+                y: 2.0,
+                z: -1.0,
             },
             color: Color{
-                red: 0.1,
-                green: 0.8,
-                blue: 0.1,
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
             },
-            intensity: 250.0,
+            intensity: 100.0,
         })],
-        bias: 1e-13,
-        max_rec: 10, 
+        bias: 1e-6,
+        max_rec: 10,
+        threads: 1,
+        samples: 4,
+        sampling: Sampling::Jittered,
+        integrator: Integrator::Whitted,
+        tone_map: ToneMap::Clamp,
+        exposure: 0.0,
+        background: Background::black(),
+        environment_samples: 0,
+        bvh: OnceLock::new(),
+        emitters: OnceLock::new(),
     };
 
-    let img: DynamicImage = render_scene(&scene);
+    let single = render_scene(&scene).unwrap();
+    scene.threads = 4;
+    let threaded = render_scene(&scene).unwrap();
 
+    assert!(single.to_rgb8().pixels().eq(threaded.to_rgb8().pixels()));
+}
 
-    DynamicImage::save(&img, &Path::new("../image.png"));
+#[test]
+fn test_srgb(){
+    assert_eq!(encode_srgb(0.0), 0.0);
+    assert!((encode_srgb(1.0) - 1.0).abs() < 1e-6);
+    assert!((encode_srgb(0.5) - 0.735_356_7).abs() < 1e-5);
+    for i in 0..=255 {
+        let encoded = i as f32 / 255.0;
+        assert!((encode_srgb(decode_srgb(encoded)) - encoded).abs() < 1e-5);
+    }
+
+    let gray = Color{ red: 0.5, green: 0.5, blue: 0.5 }.to_rgba();
+    assert_eq!(gray.0, [188, 188, 188, 255]);
+    assert_eq!(Color{ red: 2.0, green: -1.0, blue: 0.0 }.to_rgba().0, [255, 0, 0, 255]);
 
-    let img2: DynamicImage = render_scene(&scene2);
+    let mut img = DynamicImage::new_rgb8(1, 1);
+    img.as_mut_rgb8().unwrap().put_pixel(0, 0, image::Rgb([188, 188, 188]));
+    let color = LinearImage::from_image(&img, ColorSpace::Srgb);
+    assert!((color.get(0, 0).red - 0.5).abs() < 0.01);
+    let data = LinearImage::from_image(&img, ColorSpace::Linear);
+    assert_eq!(data.get(0, 0).red, 188.0 / 255.0);
+}
 
+#[test]
+fn test_fresnel(){
+    let normal = Vector3{ x: 0.0, y: 0.0, z: 1.0 };
 
-    DynamicImage::save(&img2, &Path::new("../image2.png"));
+    // Head on into glass reflects about 4%
+    let head_on = fresnel(Vector3{ x: 0.0, y: 0.0, z: -1.0 }, normal, 1.5);
+    assert!((head_on - 0.04).abs() < 1e-4);
 
+    // Grazing angles reflect almost everything
+    let grazing = fresnel(Vector3{ x: 1.0, y: 0.0, z: -0.01 }, normal, 1.5);
+    assert!(grazing > 0.9);
 
+    // From the inside past the critical angle
+    let inside = fresnel(Vector3{ x: 1.0, y: 0.0, z: 0.5 }, normal, 1.5);
+    assert_eq!(inside, 1.0);
 }
 
+#[test]
+fn test_spot_light_falloff(){
+    let spot = SpotLight{
+        pos: Point::zero(),
+        direction: Vector3{ x: 0.0, y: -1.0, z: 0.0 },
+        color: Color{ red: 1.0, green: 1.0, blue: 1.0 },
+        intensity: 100.0,
+        inner_angle: 20.0,
+        outer_angle: 40.0,
+    };
 
-fn main() {
+    assert_eq!(spot.falloff(&Point{ x: 0.0, y: -1.0, z: 0.0 }), 1.0);
+    assert_eq!(spot.falloff(&Point{ x: 1.0, y: -0.5, z: 0.0 }), 0.0);
+    assert_eq!(spot.falloff(&Point{ x: 0.0, y: 1.0, z: 0.0 }), 0.0);
 
-    let scene = Scene{
-        width: 1250,
-        height: 1000,
-        fov: 90.0, 
-        elements: vec![Element::Sphere(Sphere{  //Green ball
-            center: Point {
-                x:0.0,
-                y:0.0,
-                z:-5.0,
+    let edge = spot.falloff(&Point{ x: 30.0f64.to_radians().tan(), y: -1.0, z: 0.0 });
+    assert!(edge > 0.0 && edge < 1.0);
 
-            },
-            radius: 1.0,
-            material: Material{
-                color: Texture::color(Color{
-                    red: 0.2,
-                    green: 0.8,
-                    blue: 0.2,
-    
-                }),
-                albedo: 1.0,
-                surface: surface_type::Reflective{reflectivity: 0.1},
-            }
+    let light = Light::Spot(spot);
+    let hit_point = Point{ x: 0.0, y: -2.0, z: 0.0 };
+    assert_eq!(light.distance(&hit_point), 2.0);
+    assert!(light.intensity(&hit_point) > 0.0);
+}
 
-        }), Element::Sphere(Sphere{   //Small Yellow ball
-            center: Point {
-                x:2.0,
-                y:1.0,
-                z:-5.0,
+#[test]
+fn test_progress_and_cancel(){
+    let text = r#"{ "width": 100, "height": 100, "threads": 2,
+        "elements": [{ "type": "sphere", "center": [0, 0, -3], "radius": 1, "material": { "color": [1, 0, 0] } }],
+        "lights": [{ "type": "directional", "direction": [0, 0, -1], "color": [1, 1, 1], "intensity": 1 }] }"#;
+    let scene = parse_scene(text, "test.json", Path::new(".")).unwrap();
+
+    let mut last = None;
+    render_scene_with(&scene, &CancelToken::new(), |p| last = Some(*p)).unwrap();
+    let last = last.unwrap();
+    assert_eq!(last.tiles_done, last.tiles_total);
+    assert_eq!(last.fraction(), 1.0);
+
+    let cancel = CancelToken::new();
+    let result = render_scene_with(&scene, &cancel, |_| cancel.cancel());
+    match result {
+        Err(RenderError::Cancelled) => {},
+        _ => panic!("expected the render to be cancelled"),
+    }
+}
 
-            },
-            radius: 0.5,
-            material: Material{
-                color: Texture::color(Color{
-                    red: 0.8,
-                    green: 0.8,
-                    blue: 0.2,
-                }),
-                albedo: 1.0,
-                surface: surface_type::Transparent{index: 0.5, transparency: 0.6},
-            }
+#[test]
+fn test_elements_mut_resets_caches(){
+    let text = r#"{ "width": 10, "height": 10,
+        "elements": [{ "type": "sphere", "center": [0, 0, -5], "radius": 1, "material": { "color": [1, 0, 0] } }] }"#;
+    let mut scene = parse_scene(text, "test.json", Path::new(".")).unwrap();
+    let ray = Ray{ origin: Point::zero(), direction: Vector3{ x: 0.0, y: 0.0, z: -1.0 } };
+    assert_eq!(scene.trace(&ray).unwrap().distance, 4.0);
+
+    assert!(scene.emitters().is_empty());
+
+    let glowing = text.replace("-5", "-2").replace("[1, 0, 0] }", "[1, 0, 0], \"emission\": [1, 1, 1], \"emission_samples\": 4 }");
+    let closer = parse_scene(&glowing, "test.json", Path::new(".")).unwrap().elements_mut().pop().unwrap();
+    scene.elements_mut().push(closer);
+    assert_eq!(scene.elements().len(), 2);
+    assert_eq!(scene.trace(&ray).unwrap().distance, 1.0);
+    assert_eq!(scene.emitters(), &[1]);
+}
 
-        }), Element::Sphere(Sphere{   //Small amog us ball
-            center: Point {
-                x: -1.0,
-                y: -1.0,
-                z: -2.0,
+#[test]
+fn test_emissive_material(){
+    let text = r#"{ "width": 10, "height": 10,
+        "elements": [
+            { "type": "sphere", "center": [0, 0, -3], "radius": 0.5,
+              "material": { "color": [0, 0, 0], "emission": [1, 0.5, 0.25], "emission_samples": 8 } },
+            { "type": "plane", "center": [0, -1, 0], "normal": [0, -1, 0], "material": { "color": [1, 1, 1] } }
+        ] }"#;
+    let scene = parse_scene(text, "test.json", Path::new(".")).unwrap();
+    assert_eq!(scene.emitters(), &[0]);
+
+    let at_sphere = Ray{ origin: Point::zero(), direction: Vector3{ x: 0.0, y: 0.0, z: -1.0 } };
+    let glow = raycast(&scene, &at_sphere, 0);
+    assert!((glow.red - 1.0).abs() < 1e-6 && (glow.green - 0.5).abs() < 1e-6);
+
+    let at_floor = Ray{ origin: Point::zero(), direction: Vector3{ x: 0.0, y: -1.0, z: -3.0 }.normalize() };
+    let lit = raycast(&scene, &at_floor, 0);
+    assert!(lit.red > 0.0 && lit.red > lit.blue);
+
+    // Without samples it still glows but lights nothing
+    let dark = parse_scene(&text.replace("\"emission_samples\": 8", "\"emission_samples\": 0"), "test.json", Path::new(".")).unwrap();
+    assert_eq!(raycast(&dark, &at_floor, 0).red, 0.0);
+    assert!(raycast(&dark, &at_sphere, 0).red > 0.0);
+}
 
-            },
-            radius: 0.75,
-            material: Material{
-                color: Texture::image(image::open("test.png").unwrap()),
-                albedo: 0.75,
-                surface: surface_type::Transparent{index: 0.5, transparency: 0.6},
-            }
+#[no_mangle]
+pub extern "system" fn Java_main_hello(_env: JNIEnv, _class: JClass){
+    println!("Hello world");
+}
 
-        }), Element::Sphere(Sphere{   //Small amog us ball
-            center: Point {
-                x:-1.0,
-                y:-1.0,
-                z: 0.5,
+// Packs the image for Java as [width, height, argb, argb, ...], row by row
+pub fn to_argb(img: &DynamicImage) -> Vec<jint> {
+    let mut data = Vec::with_capacity(2 + (img.width() * img.height()) as usize);
+    data.push(img.width() as jint);
+    data.push(img.height() as jint);
 
-            },
-            radius: 0.75,
-            material: Material{
-                color: Texture::image(image::open("test.png").unwrap()),
-                albedo: 0.75,
-                surface: surface_type::Reflective{reflectivity: 0.0},
-            }
+    for pixel in img.to_rgba8().pixels() {
+        let argb = (pixel[3] as u32) << 24 | (pixel[0] as u32) << 16 | (pixel[1] as u32) << 8 | pixel[2] as u32;
+        data.push(argb as jint);
+    }
+    data
+}
 
-        }),Element::Sphere(Sphere{  //Red ball
-            center: Point {
-                x:-2.0,
-                y:2.0,
-                z:-5.0,
+// Reports to a RenderListener and asks it whether to stop, true if the listener threw
+fn report_progress(env: &JNIEnv, listener: JObject, progress: &Progress, cancel: &CancelToken) -> bool {
+    let eta = progress.eta().map(|d| d.as_secs_f64()).unwrap_or(-1.0);
+    let args = [
+        JValue::Double(progress.fraction()),
+        JValue::Double(progress.elapsed.as_secs_f64()),
+        JValue::Double(eta),
+    ];
+
+    let cancelled = env.call_method(listener, "progress", "(DDD)V", &args)
+        .and_then(|_| env.call_method(listener, "isCancelled", "()Z", &[]))
+        .and_then(|v| v.z());
+
+    match cancelled {
+        Ok(false) => false,
+        Ok(true) => {
+            cancel.cancel();
+            false
+        },
+        Err(_) => {
+            cancel.cancel();
+            true
+        },
+    }
+}
 
-            },
-            radius: 2.0,
-            material: Material{
-                color: Texture::color(Color{
-                    red: 0.8,
-                    green: 0.2,
-                    blue: 0.2,
-    
-                }),
-                albedo: 1.0,
-                surface: surface_type::Reflective{reflectivity: 1.0},
-            }
+fn render_for_java(env: &JNIEnv, scene: JString, base_dir: JString, listener: JObject) -> Result<jintArray, String> {
+    let text: String = env.get_string(scene).map_err(|e| e.to_string())?.into();
+    let base_dir: String = env.get_string(base_dir).map_err(|e| e.to_string())?.into();
 
-        }),Element::Plane(Plane{     //Plane
-            center: Point {
-                x: 0.0,
-                y: -2.0,
-                z: -5.0,
+    let scene = parse_scene(&text, "scene", Path::new(&base_dir)).map_err(|e| e.to_string())?;
 
-            },
-            normal: Vector3{
-                x: 0.0,
-                y: -1.0,
-                z: 0.0,
-            },
-            material: Material{
-                //color: Texture::color(Color{
-                    //red:0.8,
-                    //green:0.8,
-                    //blue: 0.8,
-                //}),
-                color: Texture::image(image::open("check.png").unwrap()),
-                albedo: 1.0,
-                surface: surface_type::Reflective{reflectivity: 0.99},
-            }
+    let cancel = CancelToken::new();
+    let mut listener_threw = false;
 
-        })],
-        lights: vec![Light::Directional(DirectionalLight{
-            direction: Vector3{
-                x: 0.5,
-                y: -0.25,
-                z: -0.5,
-            },
-            color: Color{
-                red: 0.8,
-                green: 0.8,
-                blue: 0.8,
-            },
-            intensity: 1.0,
-        }),Light::Directional(DirectionalLight{
-            direction: Vector3{
-                x: 0.0,
-                y: 1.0,
-                z: 0.1,
-            },
-            color: Color{
-                red: 0.1,
-                green: 0.8,
-                blue: 0.1,
-            },
-            intensity: 0.1,
-        }),Light::Point(PointLight{
-            pos: Point{
-                x: 1.0,
-                y: 2.0,
-                z:-5.0,
-            },
-            color: Color{
-                red: 0.9,
-                green: 0.2,
-                blue: 0.1,
-            },
-            intensity: 10.0,
-        }),Light::Point(PointLight{
-            pos: Point{
-                x: 0.0,
-                y: 0.0,
-                z:-1.0,
-            },
-            color: Color{
-                red: 0.1,
-                green: 0.8,
-                blue: 0.1,
-            },
-            intensity: 5.0,
-        })],
-        bias:0.1,
-        max_rec: 10, 
+    let img = render_scene_with(&scene, &cancel, |progress| {
+        if !listener.is_null() && !listener_threw {
+            listener_threw = report_progress(env, listener, progress, &cancel);
+        }
+    });
+    let img = img.map_err(|e| e.to_string())?;
+    // A throw on the last tile comes too late to cancel, and no JNI calls are allowed while it's pending
+    if listener_threw {
+        return Err("Listener threw an exception".to_string());
+    }
+    let data = to_argb(&img);
+
+    let array = env.new_int_array(data.len() as jint).map_err(|e| e.to_string())?;
+    env.set_int_array_region(array, 0, &data).map_err(|e| e.to_string())?;
+    Ok(array)
+}
+
+// Renders a JSON scene and returns the pixels, see to_argb. The listener may be null.
+// Throws a RuntimeException on errors and when the listener cancels the render.
+#[no_mangle]
+pub extern "system" fn Java_main_renderScene(env: JNIEnv, _class: JClass, scene: JString, base_dir: JString, listener: JObject) -> jintArray {
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| render_for_java(&env, scene, base_dir, listener)));
+
+    let message = match result {
+        Ok(Ok(array)) => return array,
+        Ok(Err(message)) => message,
+        Err(_) => "Rendering failed unexpectedly".to_string(),
     };
 
-    let img: DynamicImage = render_scene(&scene);
+    // An exception thrown by the listener is left for the caller
+    if !env.exception_check().unwrap_or(false) {
+        let _ = env.throw_new("java/lang/RuntimeException", message);
+    }
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn Java_main_javaCall(){
+
+    // A panic must not unwind into the JVM
+    if std::panic::catch_unwind(render_java_scenes).is_err() {
+        println!("Rendering failed unexpectedly");
+    }
+}
 
+fn render_java_scenes(){
 
-    DynamicImage::save(&img, &Path::new("../image.png"));
+    // scene.json is written by the Swing app, example.json is the showcase scene
+    let renders = [("example.json", "../image.png"), ("scene.json", "../image2.png")];
+
+    for &(scene_path, image_path) in renders.iter() {
+        let scene = match load_scene(Path::new(scene_path)) {
+            Ok(scene) => scene,
+            Err(e) => {
+                println!("Could not load {}: {}", scene_path, e);
+                continue;
+            }
+        };
 
+        let bvh = scene.bvh();
+        println!("BVH: {} nodes built in {:?}", bvh.node_count(), bvh.build_time);
 
-}
\ No newline at end of file
+        match render_scene(&scene) {
+            Ok(img) => {
+                if let Err(e) = DynamicImage::save(&img, Path::new(image_path)) {
+                    println!("Could not save {}: {}", image_path, e);
+                }
+            },
+            Err(e) => println!("Could not render {}: {}", scene_path, e),
+        }
+    }
+}
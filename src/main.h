@@ -91,6 +91,14 @@ JNIEXPORT void JNICALL Java_main_hello
 JNIEXPORT void JNICALL Java_main_javaCall
   (JNIEnv *, jclass);
 
+/*
+ * Class:     main
+ * Method:    renderScene
+ * Signature: (Ljava/lang/String;Ljava/lang/String;LRenderListener;)[I
+ */
+JNIEXPORT jintArray JNICALL Java_main_renderScene
+  (JNIEnv *, jclass, jstring, jstring, jobject);
+
 #ifdef __cplusplus
 }
 #endif
@@ -9,6 +9,7 @@ import java.net.URLDecoder;
 import java.nio.file.Path;
 import java.util.Map;
 import java.util.List;
+import java.util.concurrent.ExecutionException;
 import java.awt.BorderLayout;
 import java.awt.Color;
 import java.awt.Dimension;
@@ -16,15 +17,20 @@ import java.awt.GridLayout;
 import java.awt.HeadlessException;
 import java.awt.event.ActionEvent;
 import java.awt.event.ActionListener;
+import java.awt.image.BufferedImage;
 import java.io.IOException;
 import java.util.logging.Level;
 import java.util.logging.Logger;
 import javax.swing.JButton;
 import javax.swing.JFrame;
 import javax.swing.JLabel;
+import javax.swing.JOptionPane;
+import javax.swing.JProgressBar;
 import javax.swing.JPanel;
 import javax.swing.JSlider;
 import javax.swing.JTextField;
+import javax.swing.SwingUtilities;
+import javax.swing.SwingWorker;
 import javax.swing.border.LineBorder;
 import javax.swing.event.ChangeEvent;
 import javax.swing.event.ChangeListener;
@@ -35,6 +41,22 @@ public class main extends JFrame{
 
     private static native void javaCall();
 
+    private static native int[] renderScene(String scene, String baseDir, RenderListener listener);
+
+    /**
+     * Renders a JSON scene in-process, textures and meshes are looked up relative to the working directory.
+     * The listener may be null.
+     */
+    public static BufferedImage render(String scene, RenderListener listener) {
+        int[] data = renderScene(scene, new File(".").getAbsolutePath(), listener);
+        int width = data[0];
+        int height = data[1];
+
+        BufferedImage image = new BufferedImage(width, height, BufferedImage.TYPE_INT_ARGB);
+        image.setRGB(0, 0, width, height, data, 2, width);
+        return image;
+    }
+
     private JSlider colorSliderR;
     private JSlider colorSliderG;
     private JSlider colorSliderB;
@@ -50,6 +72,12 @@ public class main extends JFrame{
     
     private JButton renderScene;
 
+    private JButton cancelRender;
+
+    private JProgressBar renderProgress;
+
+    private volatile boolean cancelled;
+
     private JPanel settingsPanel;
 
 
@@ -76,16 +104,30 @@ public class main extends JFrame{
         renderScene.addActionListener(new ActionListener() {
             @Override
             public void actionPerformed(ActionEvent e) {
-                try {
-                    p.saveToFile();
-                    main.javaCall();
-                } catch (IOException ex) {
-                    
-                }
+                startRender(p);
+            }
+        });
+
+        cancelRender = new JButton("cancel");
+        cancelRender.setEnabled(false);
+        cancelRender.addActionListener(new ActionListener() {
+            @Override
+            public void actionPerformed(ActionEvent e) {
+                cancelled = true;
             }
         });
+
+        renderProgress = new JProgressBar(0, 1000);
+        renderProgress.setStringPainted(true);
+        renderProgress.setString("");
+
+        JPanel renderPanel = new JPanel(new BorderLayout());
+        renderPanel.add(renderScene, BorderLayout.CENTER);
+        renderPanel.add(cancelRender, BorderLayout.SOUTH);
         
-        add(renderScene, BorderLayout.EAST);
+        add(renderPanel, BorderLayout.EAST);
+
+        add(renderProgress, BorderLayout.SOUTH);
 
         addCircle.addActionListener(new ActionListener() {
             @Override
@@ -140,6 +182,53 @@ public class main extends JFrame{
         this.setDefaultCloseOperation(EXIT_ON_CLOSE);
     }
 
+    /**
+     * Renders on a background thread so the window keeps responding
+     */
+    private void startRender(PaintPanel p) {
+        String scene = p.toJson();
+        cancelled = false;
+        renderScene.setEnabled(false);
+        cancelRender.setEnabled(true);
+
+        new SwingWorker<BufferedImage, Void>() {
+            @Override
+            protected BufferedImage doInBackground() {
+                return render(scene, new RenderListener() {
+                    @Override
+                    public void progress(double fraction, double elapsed, double eta) {
+                        SwingUtilities.invokeLater(() -> {
+                            renderProgress.setValue((int) (fraction * 1000));
+                            renderProgress.setString(eta < 0 ? String.format("%.0f%%", fraction * 100)
+                                    : String.format("%.0f%%, %.0f s left", fraction * 100, eta));
+                        });
+                    }
+
+                    @Override
+                    public boolean isCancelled() {
+                        return cancelled;
+                    }
+                });
+            }
+
+            @Override
+            protected void done() {
+                renderScene.setEnabled(true);
+                cancelRender.setEnabled(false);
+                try {
+                    p.setImage(get());
+                    renderProgress.setString("done");
+                } catch (InterruptedException | ExecutionException ex) {
+                    Throwable cause = ex.getCause() != null ? ex.getCause() : ex;
+                    renderProgress.setString(cancelled ? "cancelled" : "failed");
+                    if (!cancelled) {
+                        JOptionPane.showMessageDialog(main.this, cause.getMessage(), "Could not render scene", JOptionPane.ERROR_MESSAGE);
+                    }
+                }
+            }
+        }.execute();
+    }
+
     public void initJComponents() {
         colorSliderR = new JSlider();
         colorSliderR.setMaximum(255);
@@ -0,0 +1,333 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use crate::bvh::Aabb;
+use crate::error::RenderError;
+use crate::point::Point;
+use crate::vector::Vector3;
+use crate::{Element, Intersectable, Material, Ray, Texture_thing};
+
+#[derive(Copy, Clone, Debug)]
+pub struct Face {
+    pub vertices: [usize; 3],
+    pub normals: Option<[usize; 3]>,
+    pub uvs: Option<[usize; 3]>,
+}
+
+// Triangle mesh, every face becomes its own Element::Triangle sharing the mesh
+#[derive(Debug)]
+pub struct Mesh {
+    pub vertices: Vec<Point>,
+    pub normals: Vec<Vector3>,
+    pub uvs: Vec<Texture_thing>,
+    pub faces: Vec<Face>,
+    pub material: Material,
+}
+
+impl Mesh {
+    pub fn load_obj(path: &Path, material: Material) -> Result<Mesh, RenderError> {
+        let name = path.display().to_string();
+        let file = File::open(path).map_err(|error| RenderError::Io { path: name.clone(), error })?;
+        parse_obj(BufReader::new(file), &name, material)
+    }
+
+    pub fn into_elements(self) -> Vec<Element> {
+        let mesh = Arc::new(self);
+        (0..mesh.faces.len())
+            .map(|face| Element::Triangle(Triangle { mesh: mesh.clone(), face }))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct Triangle {
+    pub mesh: Arc<Mesh>,
+    pub face: usize,
+}
+
+impl Triangle {
+    // Normal on the side the ray came from, open meshes and ones wound the wrong way get seen from behind
+    pub fn facing_normal(&self, hit_point: &Point, direction: &Vector3) -> Vector3 {
+        let (a, b, c) = self.corners();
+        let normal = self.surface_normal(hit_point);
+        if (b - a).cross(&(c - a)).dot(direction) > 0.0 {
+            Vector3::zero() - normal
+        } else {
+            normal
+        }
+    }
+
+    fn corners(&self) -> (Point, Point, Point) {
+        let v = self.mesh.faces[self.face].vertices;
+        (self.mesh.vertices[v[0]], self.mesh.vertices[v[1]], self.mesh.vertices[v[2]])
+    }
+
+    pub fn material(&self) -> &Material {
+        &self.mesh.material
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        let (a, b, c) = self.corners();
+        let corner = |p: Point| Aabb { min: p, max: p };
+        corner(a).union(&corner(b)).union(&corner(c))
+    }
+
+    // Uniform point for (s, t) in [0, 1), with the face normal and the area of the triangle
+    pub fn sample_point(&self, s: f64, t: f64) -> (Point, Vector3, f64) {
+        let (a, b, c) = self.corners();
+        let root = s.sqrt();
+        let point = a + (b - a) * (root * (1.0 - t)) + (c - a) * (root * t);
+        let cross = (b - a).cross(&(c - a));
+        (point, cross.normalize(), cross.length() / 2.0)
+    }
+
+    // Weights of the three corners for a point on the triangle
+    fn barycentric(&self, hit_point: &Point) -> (f64, f64, f64) {
+        let (a, b, c) = self.corners();
+        let ab = b - a;
+        let ac = c - a;
+        let ap = *hit_point - a;
+
+        let d00 = ab.dot(&ab);
+        let d01 = ab.dot(&ac);
+        let d11 = ac.dot(&ac);
+        let d20 = ap.dot(&ab);
+        let d21 = ap.dot(&ac);
+        let denom = d00 * d11 - d01 * d01;
+
+        let v = (d11 * d20 - d01 * d21) / denom;
+        let w = (d00 * d21 - d01 * d20) / denom;
+        (1.0 - v - w, v, w)
+    }
+}
+
+impl Intersectable for Triangle {
+    // Möller-Trumbore, hits both sides
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let (a, b, c) = self.corners();
+        let ab = b - a;
+        let ac = c - a;
+
+        let p = ray.direction.cross(&ac);
+        let det = ab.dot(&p);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = det.recip();
+
+        let t = ray.origin - a;
+        let u = t.dot(&p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = t.cross(&ab);
+        let v = ray.direction.dot(&q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let distance = ac.dot(&q) * inv_det;
+        if distance >= 0.0 {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        let face = &self.mesh.faces[self.face];
+
+        match face.normals {
+            Some(n) => {
+                let (wa, wb, wc) = self.barycentric(hit_point);
+                let normals = &self.mesh.normals;
+                (normals[n[0]] * wa + normals[n[1]] * wb + normals[n[2]] * wc).normalize()
+            }
+            None => {
+                let (a, b, c) = self.corners();
+                (b - a).cross(&(c - a)).normalize()
+            }
+        }
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> Texture_thing {
+        let (wa, wb, wc) = self.barycentric(hit_point);
+
+        match self.mesh.faces[self.face].uvs {
+            Some(t) => {
+                let uvs = &self.mesh.uvs;
+                Texture_thing {
+                    x: uvs[t[0]].x * wa as f32 + uvs[t[1]].x * wb as f32 + uvs[t[2]].x * wc as f32,
+                    y: uvs[t[0]].y * wa as f32 + uvs[t[1]].y * wb as f32 + uvs[t[2]].y * wc as f32,
+                }
+            }
+            None => Texture_thing {
+                x: wb as f32,
+                y: wc as f32,
+            },
+        }
+    }
+}
+
+fn parse_error(name: &str, line: usize, message: &str) -> RenderError {
+    RenderError::Parse {
+        file: name.to_string(),
+        line,
+        message: message.to_string(),
+    }
+}
+
+fn parse_floats(words: &[&str], count: usize, name: &str, line: usize) -> Result<Vec<f64>, RenderError> {
+    if words.len() < count {
+        return Err(parse_error(name, line, "not enough values"));
+    }
+    words[..count]
+        .iter()
+        .map(|w| w.parse::<f64>().map_err(|_| parse_error(name, line, &format!("invalid number '{}'", w))))
+        .collect()
+}
+
+// OBJ indices start at 1, negative ones count from the end
+fn parse_index(word: &str, len: usize, name: &str, line: usize) -> Result<usize, RenderError> {
+    let index = word
+        .parse::<i64>()
+        .map_err(|_| parse_error(name, line, &format!("invalid index '{}'", word)))?;
+
+    let resolved = if index < 0 { len as i64 + index } else { index - 1 };
+    if resolved < 0 || resolved >= len as i64 {
+        return Err(parse_error(name, line, &format!("index {} out of range", index)));
+    }
+    Ok(resolved as usize)
+}
+
+pub fn parse_obj<R: BufRead>(reader: R, name: &str, material: Material) -> Result<Mesh, RenderError> {
+    let mut mesh = Mesh {
+        vertices: Vec::new(),
+        normals: Vec::new(),
+        uvs: Vec::new(),
+        faces: Vec::new(),
+        material,
+    };
+
+    for (n, line) in reader.lines().enumerate() {
+        let line = line.map_err(|error| RenderError::Io { path: name.to_string(), error })?;
+        let line_number = n + 1;
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        match words.first() {
+            Some(&"v") => {
+                let v = parse_floats(&words[1..], 3, name, line_number)?;
+                mesh.vertices.push(Point { x: v[0], y: v[1], z: v[2] });
+            }
+            Some(&"vn") => {
+                let v = parse_floats(&words[1..], 3, name, line_number)?;
+                mesh.normals.push(Vector3 { x: v[0], y: v[1], z: v[2] }.normalize());
+            }
+            Some(&"vt") => {
+                let v = parse_floats(&words[1..], 2, name, line_number)?;
+                // OBJ has v going up, images have y going down
+                mesh.uvs.push(Texture_thing { x: v[0] as f32, y: 1.0 - v[1] as f32 });
+            }
+            Some(&"f") => {
+                if words.len() < 4 {
+                    return Err(parse_error(name, line_number, "face needs at least 3 vertices"));
+                }
+
+                let mut corners = Vec::new();
+                for word in &words[1..] {
+                    let parts: Vec<&str> = word.split('/').collect();
+                    let vertex = parse_index(parts[0], mesh.vertices.len(), name, line_number)?;
+                    let uv = match parts.get(1) {
+                        Some(p) if !p.is_empty() => Some(parse_index(p, mesh.uvs.len(), name, line_number)?),
+                        _ => None,
+                    };
+                    let normal = match parts.get(2) {
+                        Some(p) if !p.is_empty() => Some(parse_index(p, mesh.normals.len(), name, line_number)?),
+                        _ => None,
+                    };
+                    corners.push((vertex, uv, normal));
+                }
+
+                // Polygons are split into a fan of triangles
+                for i in 1..corners.len() - 1 {
+                    let c = [corners[0], corners[i], corners[i + 1]];
+                    mesh.faces.push(Face {
+                        vertices: [c[0].0, c[1].0, c[2].0],
+                        uvs: match (c[0].1, c[1].1, c[2].1) {
+                            (Some(a), Some(b), Some(c)) => Some([a, b, c]),
+                            _ => None,
+                        },
+                        normals: match (c[0].2, c[1].2, c[2].2) {
+                            (Some(a), Some(b), Some(c)) => Some([a, b, c]),
+                            _ => None,
+                        },
+                    });
+                }
+            }
+            // Groups, smoothing, materials and comments are ignored
+            _ => {}
+        }
+    }
+
+    Ok(mesh)
+}
+
+#[test]
+fn test_parse_obj_quad(){
+    use crate::Color;
+
+    let obj = "\
+# unit quad at z = -2
+v -1 -1 -2
+v 1 -1 -2
+v 1 1 -2
+v -1 1 -2
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1 4/4/1
+";
+    let material = Material::diffuse(Color { red: 1.0, green: 1.0, blue: 1.0 });
+
+    let elements = parse_obj(obj.as_bytes(), "quad.obj", material).unwrap().into_elements();
+    assert_eq!(elements.len(), 2);
+
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3 { x: 0.5, y: -0.5, z: -2.0 }.normalize(),
+    };
+    let (distance, element) = elements.iter()
+        .filter_map(|e| e.intersect(&ray).map(|d| (d, e)))
+        .next()
+        .unwrap();
+
+    let hit_point = ray.origin + ray.direction * distance;
+    assert!((hit_point.z + 2.0).abs() < 1e-9);
+    assert!((element.surface_normal(&hit_point).z - 1.0).abs() < 1e-9);
+
+    let coords = element.texture_coords(&hit_point);
+    assert!((coords.x - 0.75).abs() < 1e-5);
+    assert!((coords.y - 0.75).abs() < 1e-5);
+    assert!((element.facing_normal(&hit_point, &ray.direction).z - 1.0).abs() < 1e-9);
+
+    // Seen from behind the normal turns around to face the ray
+    let behind = Ray {
+        origin: Point { x: 0.5, y: -0.5, z: -4.0 },
+        direction: Vector3 { x: 0.0, y: 0.0, z: 1.0 },
+    };
+    let (distance, element) = elements.iter()
+        .filter_map(|e| e.intersect(&behind).map(|d| (d, e)))
+        .next()
+        .unwrap();
+    let hit_point = behind.origin + behind.direction * distance;
+    assert!((distance - 2.0).abs() < 1e-9);
+    assert!((element.facing_normal(&hit_point, &behind.direction).z + 1.0).abs() < 1e-9);
+
+    let bad = "v 0 0 0\nf 1 2 3\n";
+    let error = parse_obj(bad.as_bytes(), "bad.obj", Material::diffuse(Color { red: 1.0, green: 1.0, blue: 1.0 })).unwrap_err();
+    assert!(error.to_string().starts_with("bad.obj:2:"));
+}
@@ -0,0 +1,116 @@
+use crate::area_light::can_sample;
+use crate::sampling::{cosine_hemisphere, Rng};
+use crate::vector::Vector3;
+use crate::{direct_light, fresnel, surface_type, Color, Element, Intersectable, Ray, Scene};
+
+// How colors of a ray are worked out
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Integrator {
+    Whitted,      // Direct light plus mirror reflections and refractions, fast but no indirect light
+    PathTracing,  // Random bounces off diffuse surfaces too, gives color bleeding and caustics but needs many samples
+}
+
+// Bounces before russian roulette may end a path
+const MIN_BOUNCES: u32 = 3;
+
+// Glowing elements that next event estimation already aims at, running into them would count them twice
+fn is_sampled(element: &Element) -> bool {
+    can_sample(element) && element.material().emission_samples > 0
+}
+
+// One random path starting with ray, max_rec is the longest it gets
+pub fn trace_path(scene: &Scene, mut ray: Ray, rng: &mut Rng) -> Color {
+    let mut radiance = Color::black();
+    let mut throughput = Color { red: 1.0, green: 1.0, blue: 1.0 };
+    let mut count_emission = true;
+
+    for bounce in 0..scene.max_rec {
+        let intersection = match scene.trace(&ray) {
+            Some(i) => i,
+            None => {
+                radiance = radiance + throughput * scene.background.sample(&ray.direction);
+                break;
+            }
+        };
+
+        let hit_point = ray.origin + (ray.direction * intersection.distance);
+        let element = intersection.element;
+        let material = element.material();
+        let surface_color = material.color.get_color(&element.texture_coords(&hit_point));
+
+        if count_emission || !is_sampled(element) {
+            radiance = radiance + throughput.clone() * material.emitted();
+        }
+
+        let normal = element.surface_normal(&hit_point);
+        let facing = if ray.direction.dot(&normal) > 0.0 { Vector3::zero() - normal } else { normal };
+
+        // Reflective surfaces pick between their diffuse and mirror part at random
+        let diffuse_chance = match material.surface {
+            surface_type::Diffuse => 1.0,
+            surface_type::Reflective { reflectivity } => 1.0 - reflectivity,
+            surface_type::Transparent { .. } => 0.0,
+        };
+
+        if (rng.next_f64() as f32) < diffuse_chance {
+            // Next event estimation, then a cosine weighted bounce whose pdf cancels the cos / pi of the surface
+            let direct = direct_light(scene, element, hit_point, facing, rng);
+            radiance = radiance + throughput.clone() * surface_color.clone() * direct * (material.albedo / std::f32::consts::PI);
+
+            throughput = throughput * surface_color * material.albedo;
+            ray = Ray {
+                origin: hit_point + (facing * scene.bias),
+                direction: cosine_hemisphere(&facing, rng.next_f64(), rng.next_f64()),
+            };
+            count_emission = false;
+        } else {
+            ray = match material.surface {
+                surface_type::Transparent { index, transparency } => {
+                    throughput = throughput * surface_color * transparency;
+                    let kr = fresnel(ray.direction, normal, index);
+                    match Ray::create_transmission(normal, ray.direction, hit_point, scene.bias, index) {
+                        Some(transmission) if rng.next_f64() as f32 >= kr => transmission,
+                        _ => Ray::create_reflection(facing, ray.direction, hit_point, scene.bias),
+                    }
+                }
+                _ => Ray::create_reflection(facing, ray.direction, hit_point, scene.bias),
+            };
+            count_emission = true;
+        }
+
+        if bounce >= MIN_BOUNCES {
+            let survive = throughput.red.max(throughput.green).max(throughput.blue).min(0.95);
+            if rng.next_f64() as f32 >= survive {
+                break;
+            }
+            throughput = throughput * (1.0 / survive);
+        }
+    }
+
+    radiance
+}
+
+#[test]
+fn test_path_tracing_under_sky(){
+    use std::path::Path;
+    use crate::point::Point;
+
+    // Every bounce off the floor escapes to the sky, so the result is exact
+    let text = r#"{ "width": 10, "height": 10, "integrator": "path", "background": [1, 1, 1],
+        "elements": [{ "type": "plane", "center": [0, -1, 0], "normal": [0, -1, 0], "material": { "color": [0.5, 0.5, 0.5] } }] }"#;
+    let scene = crate::parse_scene(text, "test.json", Path::new(".")).unwrap();
+    assert_eq!(scene.integrator, Integrator::PathTracing);
+
+    let down = Ray { origin: Point::zero(), direction: Vector3 { x: 0.0, y: -1.0, z: -1.0 }.normalize() };
+    let mut rng = Rng::new(7);
+    for _ in 0..16 {
+        let color = trace_path(&scene, Ray { origin: down.origin, direction: down.direction }, &mut rng);
+        assert!((color.red - 0.5).abs() < 1e-6);
+    }
+
+    // A light straight above adds its direct part once
+    let lit = crate::parse_scene(&text.replace("}] }", "}],
+        \"lights\": [{ \"type\": \"directional\", \"direction\": [0, -1, 0], \"color\": [1, 1, 1], \"intensity\": 1 }] }"), "test.json", Path::new(".")).unwrap();
+    let color = trace_path(&lit, down, &mut rng);
+    assert!((color.red - (0.5 + 0.5 / std::f32::consts::PI)).abs() < 1e-6);
+}
@@ -15,6 +15,10 @@ impl Point {
     pub fn from_one(v: f64) -> Point {
         Point { x: v, y: v, z: v }
     }
+
+    pub fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
+    }
 }
 
 impl Add<Vector3> for Point {
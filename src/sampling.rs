@@ -0,0 +1,86 @@
+use std::f64::consts::PI;
+use crate::point::Point;
+use crate::vector::Vector3;
+
+// Where the samples of a pixel are placed
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Sampling {
+    Grid,      // Centers of a regular grid, always the same result
+    Jittered,  // One random point in every cell of the grid
+}
+
+// Small xorshift generator, seeded per pixel so renders don't depend on thread scheduling
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // Mix the seed so neighbouring pixels don't start out similar
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Rng { state: (z ^ (z >> 31)) | 1 }
+    }
+
+    pub fn for_pixel(x: u32, y: u32) -> Rng {
+        Rng::new(((y as u64) << 32) | x as u64)
+    }
+
+    // For sampling at a hit point, the same point always gets the same numbers
+    pub fn for_point(point: &Point) -> Rng {
+        Rng::new(point.x.to_bits() ^ point.y.to_bits().rotate_left(21) ^ point.z.to_bits().rotate_left(42))
+    }
+
+    // Uniform in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// Sub-pixel offsets in [0, 1) for every sample of one pixel
+pub fn pixel_offsets(sampling: Sampling, samples: u32, rng: &mut Rng) -> Vec<(f64, f64)> {
+    let samples = samples.max(1);
+    let columns = (samples as f64).sqrt().ceil() as u32;
+    let rows = samples.div_ceil(columns);
+
+    (0..samples)
+        .map(|i| {
+            let (cell_x, cell_y) = ((i % columns) as f64, (i / columns) as f64);
+            let (dx, dy) = match sampling {
+                Sampling::Grid => (0.5, 0.5),
+                Sampling::Jittered => (rng.next_f64(), rng.next_f64()),
+            };
+            ((cell_x + dx) / columns as f64, (cell_y + dy) / rows as f64)
+        })
+        .collect()
+}
+
+// Direction around normal for (s, t) in [0, 1), more likely the closer it is to the normal (pdf cos / pi)
+pub fn cosine_hemisphere(normal: &Vector3, s: f64, t: f64) -> Vector3 {
+    let (tangent, bitangent) = normal.orthonormal_basis();
+    let (r, phi) = (s.sqrt(), 2.0 * PI * t);
+    tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + *normal * (1.0 - s).max(0.0).sqrt()
+}
+
+#[test]
+fn test_pixel_offsets(){
+    let mut rng = Rng::for_pixel(3, 7);
+
+    assert_eq!(pixel_offsets(Sampling::Grid, 1, &mut rng), vec![(0.5, 0.5)]);
+    assert_eq!(
+        pixel_offsets(Sampling::Grid, 4, &mut rng),
+        vec![(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)]
+    );
+
+    let jittered = pixel_offsets(Sampling::Jittered, 9, &mut rng);
+    assert_eq!(jittered.len(), 9);
+    for (i, &(x, y)) in jittered.iter().enumerate() {
+        let (cell_x, cell_y) = ((i % 3) as f64 / 3.0, (i / 3) as f64 / 3.0);
+        assert!(x >= cell_x && x < cell_x + 1.0 / 3.0);
+        assert!(y >= cell_y && y < cell_y + 1.0 / 3.0);
+    }
+}
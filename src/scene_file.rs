@@ -0,0 +1,790 @@
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+use serde::Deserialize;
+use crate::camera::{Camera, FovAxis};
+use crate::csg::{Csg, Operation};
+use crate::cuboid::Cuboid;
+use crate::cylinder::{Cone, Cylinder, Disk};
+use crate::error::RenderError;
+use crate::framebuffer::ToneMap;
+use crate::mesh::Mesh;
+use crate::path_tracer::Integrator;
+use crate::point::Point;
+use crate::sampling::Sampling;
+use crate::sdf::{Sdf, SdfShape};
+use crate::torus::Torus;
+use crate::transform::Transform;
+use crate::vector::Vector3;
+use crate::area_light::{AreaLight, AreaShape};
+use crate::background::{Background, EnvironmentMap};
+use crate::{Color, ColorSpace, DirectionalLight, Element, Light, LinearImage, Material, Plane, PointLight, Scene, Sphere, SpotLight, Texture, surface_type};
+
+// Everything in a scene file, see README.md for an example
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDescription {
+    width: u32,
+    height: u32,
+    #[serde(default)]
+    camera: CameraDescription,
+    #[serde(default = "default_bias")]
+    bias: f64,
+    #[serde(default = "default_max_rec")]
+    max_rec: u32,
+    #[serde(default)]
+    threads: usize,
+    #[serde(default = "default_samples")]
+    samples: u32,
+    #[serde(default)]
+    sampling: SamplingDescription,
+    #[serde(default)]
+    integrator: IntegratorDescription,
+    #[serde(default)]
+    tone_map: ToneMapDescription,
+    #[serde(default)]
+    exposure: f32,
+    #[serde(default)]
+    background: Option<BackgroundDescription>,
+    #[serde(default)]
+    environment_samples: u32,
+    #[serde(default)]
+    elements: Vec<ElementDescription>,
+    #[serde(default)]
+    lights: Vec<LightDescription>,
+}
+
+fn default_bias() -> f64 {
+    1e-6
+}
+
+fn default_max_rec() -> u32 {
+    10
+}
+
+fn default_samples() -> u32 {
+    1
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDescription {
+    #[serde(default = "default_eye")]
+    eye: [f64; 3],
+    #[serde(default = "default_target")]
+    target: [f64; 3],
+    #[serde(default = "default_up")]
+    up: [f64; 3],
+    #[serde(default = "default_fov")]
+    fov: f64,
+    #[serde(default)]
+    fov_axis: FovAxisDescription,
+}
+
+fn default_eye() -> [f64; 3] {
+    [0.0, 0.0, 0.0]
+}
+
+fn default_target() -> [f64; 3] {
+    [0.0, 0.0, -1.0]
+}
+
+fn default_up() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn default_fov() -> f64 {
+    90.0
+}
+
+impl Default for CameraDescription {
+    fn default() -> CameraDescription {
+        CameraDescription {
+            eye: default_eye(),
+            target: default_target(),
+            up: default_up(),
+            fov: default_fov(),
+            fov_axis: FovAxisDescription::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum FovAxisDescription {
+    Horizontal,
+    #[default]
+    Vertical,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum SamplingDescription {
+    #[default]
+    Grid,
+    Jittered,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum ToneMapDescription {
+    #[default]
+    Clamp,
+    Reinhard,
+    Aces,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum IntegratorDescription {
+    #[default]
+    Whitted,
+    Path,
+}
+
+// [r, g, b], { "bottom": [r, g, b], "top": [r, g, b] } or { "image": "path/to/sky.hdr" }
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BackgroundDescription {
+    Color([f32; 3]),
+    Gradient { bottom: [f32; 3], top: [f32; 3] },
+    Image { image: String },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum ElementDescription {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: MaterialDescription,
+    },
+    Plane {
+        center: [f64; 3],
+        normal: [f64; 3],
+        material: MaterialDescription,
+    },
+    Mesh {
+        path: String,
+        material: MaterialDescription,
+    },
+    // Turned around its center by rotation, in degrees around x, y and z
+    Box {
+        min: [f64; 3],
+        max: [f64; 3],
+        #[serde(default)]
+        rotation: Option<[f64; 3]>,
+        material: MaterialDescription,
+    },
+    // Centered on center, axis points from the bottom to the top
+    Cylinder {
+        center: [f64; 3],
+        #[serde(default = "default_axis")]
+        axis: [f64; 3],
+        radius: f64,
+        height: f64,
+        material: MaterialDescription,
+    },
+    Cone {
+        center: [f64; 3],
+        #[serde(default = "default_axis")]
+        axis: [f64; 3],
+        radius: f64,
+        #[serde(default)]
+        top_radius: f64,
+        height: f64,
+        material: MaterialDescription,
+    },
+    Disk {
+        center: [f64; 3],
+        normal: [f64; 3],
+        radius: f64,
+        material: MaterialDescription,
+    },
+    // Lies flat around axis
+    Torus {
+        center: [f64; 3],
+        #[serde(default = "default_axis")]
+        axis: [f64; 3],
+        major_radius: f64,
+        minor_radius: f64,
+        material: MaterialDescription,
+    },
+    // left and right combined into one solid, shaded with the material of left
+    Csg {
+        operation: OperationDescription,
+        left: Box<ElementDescription>,
+        right: Box<ElementDescription>,
+    },
+    // Found by sphere tracing, for blobby shapes
+    Sdf {
+        shape: ShapeDescription,
+        material: MaterialDescription,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ShapeDescription {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+    },
+    Box {
+        center: [f64; 3],
+        size: [f64; 3],
+    },
+    RoundBox {
+        center: [f64; 3],
+        size: [f64; 3],
+        radius: f64,
+    },
+    Torus {
+        center: [f64; 3],
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    SmoothUnion {
+        left: Box<ShapeDescription>,
+        right: Box<ShapeDescription>,
+        blend: f64,
+    },
+    // count 0 repeats forever
+    Repeat {
+        shape: Box<ShapeDescription>,
+        spacing: [f64; 3],
+        #[serde(default)]
+        count: [u32; 3],
+    },
+}
+
+impl ShapeDescription {
+    fn into_shape(self) -> SdfShape {
+        match self {
+            ShapeDescription::Sphere { center, radius } => SdfShape::Sphere { center: point(center), radius },
+            ShapeDescription::Box { center, size } => SdfShape::Box { center: point(center), size: vector(size) },
+            ShapeDescription::RoundBox { center, size, radius } => SdfShape::RoundBox {
+                center: point(center),
+                size: vector(size),
+                radius,
+            },
+            ShapeDescription::Torus { center, major_radius, minor_radius } => SdfShape::Torus {
+                center: point(center),
+                major_radius,
+                minor_radius,
+            },
+            ShapeDescription::SmoothUnion { left, right, blend } => SdfShape::SmoothUnion {
+                left: Box::new(left.into_shape()),
+                right: Box::new(right.into_shape()),
+                blend,
+            },
+            ShapeDescription::Repeat { shape, spacing, count } => SdfShape::Repeat {
+                shape: Box::new(shape.into_shape()),
+                spacing: vector(spacing),
+                count,
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum OperationDescription {
+    Union,
+    Intersection,
+    Difference,
+}
+
+fn default_axis() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialDescription {
+    color: TextureDescription,
+    #[serde(default = "default_albedo")]
+    albedo: f32,
+    #[serde(default)]
+    surface: SurfaceDescription,
+    #[serde(default)]
+    emission: [f32; 3],
+    #[serde(default = "default_emission_strength")]
+    emission_strength: f32,
+    #[serde(default)]
+    emission_samples: u32,
+}
+
+fn default_albedo() -> f32 {
+    1.0
+}
+
+fn default_emission_strength() -> f32 {
+    1.0
+}
+
+// Either [r, g, b] or { "image": "path/to/texture.png" }, images are sRGB unless "color_space" is "linear"
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextureDescription {
+    Color([f32; 3]),
+    Image {
+        image: String,
+        #[serde(default)]
+        color_space: ColorSpaceDescription,
+    },
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum ColorSpaceDescription {
+    #[default]
+    Srgb,
+    Linear,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum SurfaceDescription {
+    #[default]
+    Diffuse,
+    Reflective { reflectivity: f32 },
+    Transparent { index: f32, transparency: f32 },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum LightDescription {
+    Directional {
+        direction: [f64; 3],
+        color: [f32; 3],
+        intensity: f32,
+    },
+    Point {
+        pos: [f64; 3],
+        color: [f32; 3],
+        intensity: f32,
+    },
+    Spot {
+        pos: [f64; 3],
+        direction: [f64; 3],
+        color: [f32; 3],
+        intensity: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+    Rectangle {
+        center: [f64; 3],
+        u: [f64; 3],
+        v: [f64; 3],
+        color: [f32; 3],
+        intensity: f32,
+        #[serde(default = "default_light_samples")]
+        samples: u32,
+    },
+    Disk {
+        center: [f64; 3],
+        normal: [f64; 3],
+        radius: f64,
+        color: [f32; 3],
+        intensity: f32,
+        #[serde(default = "default_light_samples")]
+        samples: u32,
+    },
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        color: [f32; 3],
+        intensity: f32,
+        #[serde(default = "default_light_samples")]
+        samples: u32,
+    },
+}
+
+fn default_light_samples() -> u32 {
+    16
+}
+
+fn point(p: [f64; 3]) -> Point {
+    Point { x: p[0], y: p[1], z: p[2] }
+}
+
+fn vector(v: [f64; 3]) -> Vector3 {
+    Vector3 { x: v[0], y: v[1], z: v[2] }
+}
+
+fn color(c: [f32; 3]) -> Color {
+    Color { red: c[0], green: c[1], blue: c[2] }
+}
+
+impl MaterialDescription {
+    fn into_material(self, base_dir: &Path) -> Result<Material, RenderError> {
+        let texture = match self.color {
+            TextureDescription::Color(c) => Texture::color(color(c)),
+            TextureDescription::Image { image, color_space } => {
+                let path = base_dir.join(&image);
+                let img = image::open(&path).map_err(|e| RenderError::MissingTexture {
+                    path: path.display().to_string(),
+                    message: e.to_string(),
+                })?;
+                let color_space = match color_space {
+                    ColorSpaceDescription::Srgb => ColorSpace::Srgb,
+                    ColorSpaceDescription::Linear => ColorSpace::Linear,
+                };
+                Texture::image(LinearImage::from_image(&img, color_space))
+            }
+        };
+
+        let surface = match self.surface {
+            SurfaceDescription::Diffuse => surface_type::Diffuse,
+            SurfaceDescription::Reflective { reflectivity } => surface_type::Reflective { reflectivity },
+            SurfaceDescription::Transparent { index, transparency } => surface_type::Transparent { index, transparency },
+        };
+
+        Ok(Material {
+            color: texture,
+            albedo: self.albedo,
+            surface,
+            emission: color(self.emission),
+            emission_strength: self.emission_strength,
+            emission_samples: self.emission_samples,
+        })
+    }
+}
+
+impl ElementDescription {
+    // A mesh turns into one element per triangle, the rest into just one
+    fn into_elements(self, base_dir: &Path, elements: &mut Vec<Element>) -> Result<(), RenderError> {
+        match self {
+            ElementDescription::Sphere { center, radius, material } => {
+                elements.push(Element::Sphere(Sphere {
+                    center: point(center),
+                    radius,
+                    material: material.into_material(base_dir)?,
+                }));
+            }
+            ElementDescription::Plane { center, normal, material } => {
+                elements.push(Element::Plane(Plane {
+                    center: point(center),
+                    normal: vector(normal),
+                    material: material.into_material(base_dir)?,
+                }));
+            }
+            ElementDescription::Mesh { path, material } => {
+                let mesh = Mesh::load_obj(&base_dir.join(&path), material.into_material(base_dir)?)?;
+                elements.extend(mesh.into_elements());
+            }
+            ElementDescription::Box { min, max, rotation, material } => {
+                let (min, max) = (point(min), point(max));
+                let cuboid = match rotation {
+                    None => Cuboid { min, max, transform: None, material: material.into_material(base_dir)? },
+                    Some(rotation) => {
+                        let half = (max - min) * 0.5;
+                        Cuboid {
+                            min: Point::zero() - half,
+                            max: Point::zero() + half,
+                            transform: Some(Transform::new(min + half - Point::zero(), vector(rotation))),
+                            material: material.into_material(base_dir)?,
+                        }
+                    }
+                };
+                elements.push(Element::Box(cuboid));
+            }
+            ElementDescription::Cylinder { center, axis, radius, height, material } => {
+                elements.push(Element::Cylinder(Cylinder {
+                    radius,
+                    height,
+                    transform: Transform::with_axis(vector(center), &vector(axis)),
+                    material: material.into_material(base_dir)?,
+                }));
+            }
+            ElementDescription::Cone { center, axis, radius, top_radius, height, material } => {
+                elements.push(Element::Cone(Cone {
+                    radius,
+                    top_radius,
+                    height,
+                    transform: Transform::with_axis(vector(center), &vector(axis)),
+                    material: material.into_material(base_dir)?,
+                }));
+            }
+            ElementDescription::Torus { center, axis, major_radius, minor_radius, material } => {
+                elements.push(Element::Torus(Torus {
+                    major_radius,
+                    minor_radius,
+                    transform: Transform::with_axis(vector(center), &vector(axis)),
+                    material: material.into_material(base_dir)?,
+                }));
+            }
+            ElementDescription::Disk { center, normal, radius, material } => {
+                elements.push(Element::Disk(Disk {
+                    center: point(center),
+                    normal: vector(normal),
+                    radius,
+                    material: material.into_material(base_dir)?,
+                }));
+            }
+            ElementDescription::Sdf { shape, material } => {
+                elements.push(Element::Sdf(Sdf {
+                    shape: shape.into_shape(),
+                    material: material.into_material(base_dir)?,
+                }));
+            }
+            ElementDescription::Csg { operation, left, right } => {
+                let operation = match operation {
+                    OperationDescription::Union => Operation::Union,
+                    OperationDescription::Intersection => Operation::Intersection,
+                    OperationDescription::Difference => Operation::Difference,
+                };
+                elements.push(Element::Csg(Csg {
+                    operation,
+                    left: Box::new(left.into_part(base_dir)?),
+                    right: Box::new(right.into_part(base_dir)?),
+                }));
+            }
+        }
+        Ok(())
+    }
+
+    // A csg needs a single solid on each side
+    fn into_part(self, base_dir: &Path) -> Result<Element, RenderError> {
+        let mut elements = Vec::new();
+        self.into_elements(base_dir, &mut elements)?;
+        if elements.len() != 1 {
+            return Err(RenderError::InvalidGeometry("a mesh can't be part of a csg".to_string()));
+        }
+        Ok(elements.remove(0))
+    }
+}
+
+impl SceneDescription {
+    fn into_scene(self, base_dir: &Path) -> Result<Scene, RenderError> {
+        let mut elements = Vec::new();
+
+        for element in self.elements {
+            element.into_elements(base_dir, &mut elements)?;
+        }
+
+        let lights = self.lights.into_iter().map(|light| match light {
+            LightDescription::Directional { direction, color: c, intensity } => Light::Directional(DirectionalLight {
+                direction: vector(direction),
+                color: color(c),
+                intensity,
+            }),
+            LightDescription::Point { pos, color: c, intensity } => Light::Point(PointLight {
+                pos: point(pos),
+                color: color(c),
+                intensity,
+            }),
+            LightDescription::Spot { pos, direction, color: c, intensity, inner_angle, outer_angle } => Light::Spot(SpotLight {
+                pos: point(pos),
+                direction: vector(direction),
+                color: color(c),
+                intensity,
+                inner_angle,
+                outer_angle,
+            }),
+            LightDescription::Rectangle { center, u, v, color: c, intensity, samples } => Light::Area(AreaLight {
+                shape: AreaShape::Rectangle { center: point(center), u: vector(u), v: vector(v) },
+                color: color(c),
+                intensity,
+                samples,
+            }),
+            LightDescription::Disk { center, normal, radius, color: c, intensity, samples } => Light::Area(AreaLight {
+                shape: AreaShape::Disk { center: point(center), normal: vector(normal), radius },
+                color: color(c),
+                intensity,
+                samples,
+            }),
+            LightDescription::Sphere { center, radius, color: c, intensity, samples } => Light::Area(AreaLight {
+                shape: AreaShape::Sphere { center: point(center), radius },
+                color: color(c),
+                intensity,
+                samples,
+            }),
+        }).collect();
+
+        let background = match self.background {
+            None => Background::black(),
+            Some(BackgroundDescription::Color(c)) => Background::Color(color(c)),
+            Some(BackgroundDescription::Gradient { bottom, top }) => Background::Gradient { bottom: color(bottom), top: color(top) },
+            Some(BackgroundDescription::Image { image }) => Background::Image(EnvironmentMap::load(&base_dir.join(image))?),
+        };
+
+        let mut camera = Camera::new(
+            point(self.camera.eye),
+            point(self.camera.target),
+            vector(self.camera.up),
+            self.camera.fov,
+        );
+        camera.fov_axis = match self.camera.fov_axis {
+            FovAxisDescription::Horizontal => FovAxis::Horizontal,
+            FovAxisDescription::Vertical => FovAxis::Vertical,
+        };
+
+        Ok(Scene {
+            width: self.width,
+            height: self.height,
+            camera,
+            elements,
+            lights,
+            bias: self.bias,
+            max_rec: self.max_rec,
+            threads: self.threads,
+            samples: self.samples,
+            sampling: match self.sampling {
+                SamplingDescription::Grid => Sampling::Grid,
+                SamplingDescription::Jittered => Sampling::Jittered,
+            },
+            integrator: match self.integrator {
+                IntegratorDescription::Whitted => Integrator::Whitted,
+                IntegratorDescription::Path => Integrator::PathTracing,
+            },
+            tone_map: match self.tone_map {
+                ToneMapDescription::Clamp => ToneMap::Clamp,
+                ToneMapDescription::Reinhard => ToneMap::Reinhard,
+                ToneMapDescription::Aces => ToneMap::Aces,
+            },
+            exposure: self.exposure,
+            background,
+            environment_samples: self.environment_samples,
+            bvh: OnceLock::new(),
+            emitters: OnceLock::new(),
+        })
+    }
+}
+
+// Textures and meshes are looked up relative to base_dir
+pub fn parse_scene(text: &str, name: &str, base_dir: &Path) -> Result<Scene, RenderError> {
+    let description: SceneDescription = serde_json::from_str(text).map_err(|e| {
+        // serde_json puts the position last, the line is reported separately
+        let message = e.to_string();
+        RenderError::Parse {
+            file: name.to_string(),
+            line: e.line(),
+            message: message.split(" at line ").next().unwrap_or(&message).to_string(),
+        }
+    })?;
+
+    let scene = description.into_scene(base_dir)?;
+    scene.validate()?;
+    Ok(scene)
+}
+
+pub fn load_scene(path: &Path) -> Result<Scene, RenderError> {
+    let text = fs::read_to_string(path).map_err(|error| RenderError::Io {
+        path: path.display().to_string(),
+        error,
+    })?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    parse_scene(&text, &path.display().to_string(), base_dir)
+}
+
+#[test]
+fn test_parse_scene(){
+    let text = r#"{
+        "width": 320,
+        "height": 240,
+        "camera": { "eye": [0, 1, 5], "target": [0, 0, -5], "fov": 60, "fov_axis": "horizontal" },
+        "samples": 4,
+        "sampling": "jittered",
+        "tone_map": "aces",
+        "exposure": -1,
+        "background": { "bottom": [0, 0, 0], "top": [0.5, 0.7, 1] },
+        "environment_samples": 8,
+        "elements": [
+            { "type": "sphere", "center": [0, 0, -5], "radius": 1,
+              "material": { "color": [0.2, 0.8, 0.2], "surface": { "type": "reflective", "reflectivity": 0.1 } } },
+            { "type": "plane", "center": [0, -2, 0], "normal": [0, -1, 0],
+              "material": { "color": [0.8, 0.8, 0.8], "albedo": 0.3 } },
+            { "type": "box", "min": [-1, -2, -8], "max": [1, -1, -6], "rotation": [0, 30, 0],
+              "material": { "color": [0.5, 0.3, 0.1] } },
+            { "type": "cylinder", "center": [2, -1, -6], "radius": 0.5, "height": 2, "material": { "color": [1, 1, 1] } },
+            { "type": "cone", "center": [-2, -1, -6], "axis": [0, 0, 1], "radius": 0.5, "top_radius": 0.2, "height": 1,
+              "material": { "color": [1, 1, 1] } },
+            { "type": "disk", "center": [0, -1.9, -4], "normal": [0, 1, 0], "radius": 0.5, "material": { "color": [1, 1, 1] } },
+            { "type": "torus", "center": [0, 1, -6], "axis": [0, 0, 1], "major_radius": 1, "minor_radius": 0.25,
+              "material": { "color": [1, 1, 1] } },
+            { "type": "csg", "operation": "difference",
+              "left": { "type": "sphere", "center": [3, 0, -6], "radius": 1, "material": { "color": [1, 1, 1] } },
+              "right": { "type": "box", "min": [3, 0, -6], "max": [4, 1, -5], "material": { "color": [1, 1, 1] } } },
+            { "type": "sdf", "material": { "color": [1, 1, 1] }, "shape": { "type": "smooth_union", "blend": 0.5,
+              "left": { "type": "round_box", "center": [-3, 0, -6], "size": [1, 1, 1], "radius": 0.1 },
+              "right": { "type": "repeat", "spacing": [0.5, 0, 0], "count": [3, 0, 0],
+                         "shape": { "type": "sphere", "center": [-3, 1, -6], "radius": 0.2 } } } }
+        ],
+        "lights": [
+            { "type": "directional", "direction": [0.5, -0.25, -0.5], "color": [1, 1, 1], "intensity": 1 },
+            { "type": "point", "pos": [0, 5, 0], "color": [1, 0.5, 0.5], "intensity": 500 },
+            { "type": "spot", "pos": [0, 5, -5], "direction": [0, -1, 0], "color": [1, 1, 1], "intensity": 500,
+              "inner_angle": 15, "outer_angle": 30 },
+            { "type": "rectangle", "center": [0, 4, -5], "u": [1, 0, 0], "v": [0, 0, 1], "color": [1, 1, 1], "intensity": 300 },
+            { "type": "sphere", "center": [2, 4, -5], "radius": 0.5, "color": [1, 1, 1], "intensity": 300, "samples": 4 }
+        ]
+    }"#;
+
+    let scene = parse_scene(text, "test.json", Path::new(".")).unwrap();
+    assert_eq!(scene.width, 320);
+    assert_eq!(scene.elements.len(), 9);
+    assert_eq!(scene.lights.len(), 5);
+    match scene.lights[3] {
+        Light::Area(ref a) => assert_eq!(a.samples, 16),
+        _ => panic!("expected an area light"),
+    }
+    assert_eq!(scene.samples, 4);
+    assert_eq!(scene.sampling, Sampling::Jittered);
+    assert_eq!(scene.camera.fov_axis, FovAxis::Horizontal);
+    assert_eq!(scene.max_rec, 10);
+    assert_eq!(scene.environment_samples, 8);
+    assert_eq!(scene.tone_map, ToneMap::Aces);
+    assert_eq!(scene.exposure, -1.0);
+    match scene.background {
+        Background::Gradient { ref top, .. } => assert_eq!(top.blue, 1.0),
+        _ => panic!("expected a gradient background"),
+    }
+    match scene.elements[1].material().surface {
+        surface_type::Diffuse => {},
+        _ => panic!("plane should default to diffuse"),
+    }
+
+    let error = parse_scene("{ \"width\": 10,\n \"height\": 10,\n \"elements\": [{ \"type\": \"cube\" }] }", "bad.json", Path::new("."));
+    match error {
+        Err(RenderError::Parse { line, .. }) => assert_eq!(line, 3),
+        _ => panic!("expected a parse error"),
+    }
+
+    let missing = r#"{ "width": 10, "height": 10, "elements": [{ "type": "sphere", "center": [0, 0, -1], "radius": 1,
+        "material": { "color": { "image": "no/such/texture.png" } } }] }"#;
+    match parse_scene(missing, "missing.json", Path::new(".")) {
+        Err(RenderError::MissingTexture { .. }) => {},
+        _ => panic!("expected a missing texture"),
+    }
+
+    let flat_cylinder = r#"{ "width": 10, "height": 10, "elements": [{ "type": "cylinder", "center": [0, 0, -1], "axis": [0, 0, 0],
+        "radius": 1, "height": 1, "material": { "color": [1, 1, 1] } }] }"#;
+    assert!(parse_scene(flat_cylinder, "flat.json", Path::new(".")).is_err());
+
+    let flat = r#"{ "width": 10, "height": 10, "elements": [{ "type": "sphere", "center": [0, 0, -1], "radius": 0,
+        "material": { "color": [1, 1, 1] } }] }"#;
+    match parse_scene(flat, "flat.json", Path::new(".")) {
+        Err(RenderError::InvalidGeometry(_)) => {},
+        _ => panic!("expected invalid geometry"),
+    }
+
+    let flat_part = r#"{ "width": 10, "height": 10, "elements": [{ "type": "csg", "operation": "intersection",
+        "left": { "type": "sphere", "center": [0, 0, -3], "radius": 1, "material": { "color": [1, 1, 1] } },
+        "right": { "type": "disk", "center": [0, 0, -3], "normal": [0, 0, 1], "radius": 1, "material": { "color": [1, 1, 1] } } }] }"#;
+    match parse_scene(flat_part, "flat.json", Path::new(".")) {
+        Err(RenderError::InvalidGeometry(_)) => {},
+        _ => panic!("expected invalid geometry"),
+    }
+
+    let data = r#"{ "width": 10, "height": 10, "elements": [{ "type": "sphere", "center": [0, 0, -1], "radius": 1,
+        "material": { "color": { "image": "texture/check.png", "color_space": "linear" } } }] }"#;
+    assert!(parse_scene(data, "data.json", Path::new("src")).is_ok());
+
+    let example = load_scene(Path::new("src/example.json")).unwrap();
+    assert_eq!(example.elements.len(), 9);
+    assert_eq!(example.lights.len(), 4);
+}
@@ -0,0 +1,426 @@
+use crate::bvh::Aabb;
+use crate::csg::pair_hits;
+use crate::point::Point;
+use crate::vector::Vector3;
+use crate::{Intersectable, Material, Ray, Texture_thing};
+
+// Shapes given by how far every point is from their surface, negative inside. Rays find them by
+// sphere tracing: nothing is closer than the distance, so the ray can safely step that far.
+
+const MAX_STEPS: u32 = 512;
+const STEPS_PER_UNIT: f64 = 16.0;  // On top of MAX_STEPS, so long marches don't run out
+const MAX_DISTANCE: f64 = 1e4;  // How far rays look for shapes that go on forever
+const REACH: f64 = 100.0;  // How far around the origin intervals look for them when nothing else limits it
+const MIN_STEP: f64 = 1e-4;  // Steps never get shorter than this so rays get through the surface
+const GRAZE: f64 = 10.0 * MIN_STEP;  // Rays that run out of steps this close to the surface hit it
+const NORMAL_STEP: f64 = 1e-6;
+
+#[derive(Debug)]
+pub enum SdfShape {
+    Sphere { center: Point, radius: f64 },
+    Box { center: Point, size: Vector3 },
+    // size is the outside, the corners are rounded off by radius
+    RoundBox { center: Point, size: Vector3, radius: f64 },
+    // Lies flat, around the y axis
+    Torus { center: Point, major_radius: f64, minor_radius: f64 },
+    // Melts the two together over about blend, 0 is a plain union
+    SmoothUnion { left: Box<SdfShape>, right: Box<SdfShape>, blend: f64 },
+    // Copies of shape spacing apart, count copies along each axis starting at the original or
+    // forever both ways with 0. A spacing of 0 doesn't repeat along that axis. The shape has to
+    // fit in a cell.
+    Repeat { shape: Box<SdfShape>, spacing: Vector3, count: [u32; 3] },
+}
+
+fn length(x: f64, y: f64, z: f64) -> f64 {
+    (x * x + y * y + z * z).sqrt()
+}
+
+fn box_distance(p: Vector3, half: Vector3) -> f64 {
+    let (x, y, z) = (p.x.abs() - half.x, p.y.abs() - half.y, p.z.abs() - half.z);
+    length(x.max(0.0), y.max(0.0), z.max(0.0)) + x.max(y).max(z).min(0.0)
+}
+
+// Moves p back by whole cells to the copy around start it is closest to, as far as there are copies
+fn repeat(p: f64, start: f64, spacing: f64, count: u32) -> f64 {
+    if spacing == 0.0 {
+        return p;
+    }
+    let mut cell = ((p - start) / spacing).round();
+    if count > 0 {
+        cell = cell.max(0.0).min((count - 1) as f64);
+    }
+    p - spacing * cell
+}
+
+impl SdfShape {
+    pub fn distance(&self, p: &Point) -> f64 {
+        match *self {
+            SdfShape::Sphere { center, radius } => (*p - center).length() - radius,
+            SdfShape::Box { center, size } => box_distance(*p - center, size * 0.5),
+            SdfShape::RoundBox { center, size, radius } => {
+                box_distance(*p - center, size * 0.5 - Vector3::one_to_all(radius)) - radius
+            },
+            SdfShape::Torus { center, major_radius, minor_radius } => {
+                let q = *p - center;
+                let out = (q.x * q.x + q.z * q.z).sqrt() - major_radius;
+                (out * out + q.y * q.y).sqrt() - minor_radius
+            },
+            SdfShape::SmoothUnion { ref left, ref right, blend } => {
+                let (a, b) = (left.distance(p), right.distance(p));
+                if blend <= 0.0 {
+                    return a.min(b);
+                }
+                let h = (blend - (a - b).abs()).max(0.0) / blend;
+                a.min(b) - h * h * blend * 0.25
+            },
+            SdfShape::Repeat { ref shape, spacing, count } => {
+                // Cells are counted from the middle of the shape
+                let start = shape.bounds().map_or(Point::zero(), |b| b.center());
+                shape.distance(&Point {
+                    x: repeat(p.x, start.x, spacing.x, count[0]),
+                    y: repeat(p.y, start.y, spacing.y, count[1]),
+                    z: repeat(p.z, start.z, spacing.z, count[2]),
+                })
+            },
+        }
+    }
+
+    // None for shapes that go on forever
+    pub fn bounds(&self) -> Option<Aabb> {
+        let around = |center: Point, half: Vector3| Some(Aabb { min: center - half, max: center + half });
+        match *self {
+            SdfShape::Sphere { center, radius } => around(center, Vector3::one_to_all(radius)),
+            SdfShape::Box { center, size } | SdfShape::RoundBox { center, size, .. } => around(center, size * 0.5),
+            SdfShape::Torus { center, major_radius, minor_radius } => {
+                let outer = major_radius + minor_radius;
+                around(center, Vector3 { x: outer, y: minor_radius, z: outer })
+            },
+            SdfShape::SmoothUnion { ref left, ref right, blend } => {
+                // The blend can bulge out by up to a quarter of it
+                let bounds = left.bounds()?.union(&right.bounds()?);
+                let grow = Vector3::one_to_all(blend.max(0.0) * 0.25);
+                Some(Aabb { min: bounds.min - grow, max: bounds.max + grow })
+            },
+            SdfShape::Repeat { ref shape, spacing, count } => {
+                let bounds = shape.bounds()?;
+                let spacing = [spacing.x, spacing.y, spacing.z];
+                let mut last = [0.0; 3];
+                for i in 0..3 {
+                    if spacing[i] != 0.0 {
+                        if count[i] == 0 {
+                            return None;
+                        }
+                        last[i] = spacing[i] * (count[i] - 1) as f64;
+                    }
+                }
+                let last = Vector3 { x: last[0], y: last[1], z: last[2] };
+                Some(bounds.union(&Aabb { min: bounds.min + last, max: bounds.max + last }))
+            },
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let positive = |size: &Vector3| size.is_finite() && size.x > 0.0 && size.y > 0.0 && size.z > 0.0;
+        match *self {
+            SdfShape::Sphere { center, radius } => center.is_finite() && radius > 0.0 && radius.is_finite(),
+            SdfShape::Box { center, size } => center.is_finite() && positive(&size),
+            SdfShape::RoundBox { center, size, radius } => {
+                center.is_finite() && positive(&size) && radius >= 0.0 && 2.0 * radius <= size.x.min(size.y).min(size.z)
+            },
+            SdfShape::Torus { center, major_radius, minor_radius } => {
+                center.is_finite() && major_radius > 0.0 && minor_radius > 0.0 && major_radius.is_finite() && minor_radius.is_finite()
+            },
+            SdfShape::SmoothUnion { ref left, ref right, blend } => left.is_valid() && right.is_valid() && blend >= 0.0 && blend.is_finite(),
+            SdfShape::Repeat { ref shape, spacing, .. } => {
+                shape.is_valid() && spacing.is_finite() && spacing.x >= 0.0 && spacing.y >= 0.0 && spacing.z >= 0.0
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Sdf {
+    pub shape: SdfShape,
+    pub material: Material,
+}
+
+impl Sdf {
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        self.shape.bounds()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.shape.is_valid()
+    }
+
+    // Part of the ray between from and to worth marching along. Flat sides can be right on the
+    // bounds, so they are grown a bit to always start outside.
+    fn span(&self, ray: &Ray, from: f64, to: f64) -> Option<(f64, f64)> {
+        match self.shape.bounds() {
+            Some(bounds) => {
+                let grow = Vector3::one_to_all(MIN_STEP);
+                Aabb { min: bounds.min - grow, max: bounds.max + grow }.span(ray, from, to)
+            },
+            None if from < to => Some((from, to)),
+            None => None,
+        }
+    }
+
+    // Distances where the ray goes through the surface between from and to, just the first one if first
+    fn march(&self, ray: &Ray, from: f64, to: f64, first: bool) -> Vec<f64> {
+        let at = |t: f64| self.shape.distance(&(ray.origin + ray.direction * t));
+        let speed = ray.direction.length();
+        let steps = MAX_STEPS + ((to - from) * speed * STEPS_PER_UNIT).min(1e6) as u32;
+        let mut hits = Vec::new();
+
+        let mut t = from;
+        let mut distance = at(t);
+        let mut closest = (t, distance.abs());
+        for _ in 0..steps {
+            if t >= to {
+                return hits;
+            }
+            let next = (t + distance.abs().max(MIN_STEP) / speed).min(to);
+            let next_distance = at(next);
+            if (next_distance < 0.0) != (distance < 0.0) {
+                hits.push(self.refine(&at, t, next));
+                if first {
+                    return hits;
+                }
+            }
+            t = next;
+            distance = next_distance;
+            if distance.abs() < closest.1 {
+                closest = (t, distance.abs());
+            }
+        }
+
+        // Out of steps, which happens when the ray grazes the surface. If it came close enough it
+        // hit it there, otherwise it only went past something slowly.
+        if first && t < to && closest.1 < GRAZE {
+            hits.push(closest.0);
+        }
+        hits
+    }
+
+    // Bisects down to the surface, the result stays on the side the ray came from
+    fn refine<F: Fn(f64) -> f64>(&self, at: &F, mut before: f64, mut after: f64) -> f64 {
+        let inside = at(before) < 0.0;
+        for _ in 0..24 {
+            let middle = 0.5 * (before + after);
+            if (at(middle) < 0.0) == inside {
+                before = middle;
+            } else {
+                after = middle;
+            }
+        }
+        before
+    }
+}
+
+impl Intersectable for Sdf {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let (from, to) = self.span(ray, 0.0, MAX_DISTANCE)?;
+        self.march(ray, from, to, true).first().cloned()
+    }
+
+    // Gradient of the distance, by central differences
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        let along = |x: f64, y: f64, z: f64| {
+            let step = Vector3 { x, y, z } * NORMAL_STEP;
+            self.shape.distance(&(*hit_point + step)) - self.shape.distance(&(*hit_point - step))
+        };
+        Vector3 {
+            x: along(1.0, 0.0, 0.0),
+            y: along(0.0, 1.0, 0.0),
+            z: along(0.0, 0.0, 1.0),
+        }.normalize()
+    }
+
+    // Flat projection along the axis the normal is closest to, repeating like on planes
+    fn texture_coords(&self, hit_point: &Point) -> Texture_thing {
+        let n = self.surface_normal(hit_point);
+        let p = hit_point;
+        let (x, y) = if n.x.abs() >= n.y.abs() && n.x.abs() >= n.z.abs() {
+            (p.z, p.y)
+        } else if n.y.abs() >= n.z.abs() {
+            (p.x, p.z)
+        } else {
+            (p.x, p.y)
+        };
+        Texture_thing {
+            x: x as f32,
+            y: y as f32,
+        }
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        self.intervals_within(ray, f64::NEG_INFINITY, f64::INFINITY)
+    }
+
+    fn intervals_within(&self, ray: &Ray, from: f64, to: f64) -> Vec<(f64, f64)> {
+        // Without bounds there's no end to the march, so it needs one
+        let reach = if self.shape.bounds().is_some() { MAX_DISTANCE } else { REACH };
+        let from = if from.is_finite() { from } else { -reach };
+        let to = if to.is_finite() { to } else { reach };
+        let (from, to) = match self.span(ray, from, to) {
+            Some(span) => span,
+            None => return Vec::new(),
+        };
+        let mut hits = self.march(ray, from, to, false);
+
+        // The march can start or end inside, those stretches go on past it
+        if self.shape.distance(&(ray.origin + ray.direction * from)) < 0.0 {
+            hits.insert(0, f64::NEG_INFINITY);
+        }
+        if hits.len() % 2 == 1 {
+            hits.push(f64::INFINITY);
+        }
+        pair_hits(&hits)
+    }
+}
+
+#[test]
+fn test_sdf_distance(){
+    let origin = Point::zero();
+    let point = |x: f64, y: f64, z: f64| Point { x, y, z };
+    let near = |a: f64, b: f64| (a - b).abs() < 1e-9;
+
+    let sphere = || Box::new(SdfShape::Sphere { center: origin, radius: 1.0 });
+    assert!(near(sphere().distance(&point(3.0, 0.0, 0.0)), 2.0));
+    assert!(near(sphere().distance(&origin), -1.0));
+
+    let cube = SdfShape::Box { center: origin, size: Vector3::one_to_all(2.0) };
+    assert!(near(cube.distance(&point(3.0, 0.0, 0.0)), 2.0));
+    assert!(near(cube.distance(&point(2.0, 2.0, 1.0)), 2f64.sqrt()));
+    assert!(near(cube.distance(&point(0.5, 0.0, 0.0)), -0.5));
+
+    let rounded = SdfShape::RoundBox { center: origin, size: Vector3::one_to_all(2.0), radius: 0.5 };
+    assert!(near(rounded.distance(&point(2.0, 0.0, 0.0)), 1.0));
+    assert!(near(rounded.distance(&point(2.0, 2.0, 0.0)), 1.5 * 2f64.sqrt() - 0.5));
+
+    let torus = SdfShape::Torus { center: origin, major_radius: 2.0, minor_radius: 0.5 };
+    assert!(near(torus.distance(&point(0.0, 0.0, 2.0)), -0.5));
+    assert!(near(torus.distance(&origin), 1.5));
+
+    // Where the two are close the blend fills the gap in
+    let apart = |blend| SdfShape::SmoothUnion {
+        left: sphere(),
+        right: Box::new(SdfShape::Sphere { center: point(2.5, 0.0, 0.0), radius: 1.0 }),
+        blend,
+    };
+    assert!(near(apart(0.0).distance(&point(1.25, 0.0, 0.0)), 0.25));
+    assert!(apart(2.0).distance(&point(1.25, 0.0, 0.0)) < 0.0);
+
+    // Three copies along x, none past the last one
+    let row = SdfShape::Repeat { shape: sphere(), spacing: Vector3 { x: 3.0, y: 0.0, z: 0.0 }, count: [3, 0, 0] };
+    assert!(near(row.distance(&point(6.0, 0.0, 0.0)), -1.0));
+    assert!(near(row.distance(&point(12.0, 0.0, 0.0)), 5.0));
+    let bounds = row.bounds().unwrap();
+    assert!(near(bounds.min.x, -1.0) && near(bounds.max.x, 7.0));
+    let forever = SdfShape::Repeat { shape: sphere(), spacing: Vector3 { x: 3.0, y: 0.0, z: 0.0 }, count: [0, 0, 0] };
+    assert!(near(forever.distance(&point(300.0, 0.0, 0.0)), -1.0));
+    assert!(forever.bounds().is_none());
+}
+
+#[test]
+fn test_sdf(){
+    use crate::Color;
+
+    let sdf = Sdf {
+        shape: SdfShape::RoundBox {
+            center: Point { x: 0.0, y: 0.0, z: -5.0 },
+            size: Vector3::one_to_all(2.0),
+            radius: 0.25,
+        },
+        material: Material::diffuse(Color::black()),
+    };
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+    };
+
+    let t = sdf.intersect(&ray).unwrap();
+    assert!((t - 4.0).abs() < 1e-6);
+    let normal = sdf.surface_normal(&(ray.origin + ray.direction * t));
+    assert!((normal.z - 1.0).abs() < 1e-6);
+
+    // The rounded corner is missed by a ray the sharp one would stop
+    let corner = Ray {
+        origin: Point { x: 0.97, y: 0.97, z: 0.0 },
+        direction: Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+    };
+    assert!(sdf.intersect(&corner).is_none());
+
+    // From inside the ray finds the way out, and csg gets the whole way through
+    let inside = Ray {
+        origin: Point { x: 0.0, y: 0.0, z: -5.0 },
+        direction: Vector3 { x: 1.0, y: 0.0, z: 0.0 },
+    };
+    assert!((sdf.intersect(&inside).unwrap() - 1.0).abs() < 1e-6);
+    let intervals = sdf.intervals(&inside);
+    assert_eq!(intervals.len(), 1);
+    assert!((intervals[0].0 + 1.0).abs() < 1e-6 && (intervals[0].1 - 1.0).abs() < 1e-6);
+
+    let behind = Ray {
+        origin: Point::zero(),
+        direction: Vector3 { x: 0.0, y: 0.0, z: 1.0 },
+    };
+    assert!(sdf.intersect(&behind).is_none());
+
+    // Down a long narrow gap the steps run out without ever getting near a wall
+    let wall = |x: f64| Box::new(SdfShape::Box {
+        center: Point { x, y: 0.0, z: -500.0 },
+        size: Vector3 { x: 2.0, y: 2.0, z: 1000.0 },
+    });
+    let gap = Sdf {
+        shape: SdfShape::SmoothUnion { left: wall(-1.01), right: wall(1.01), blend: 0.0 },
+        material: Material::diffuse(Color::black()),
+    };
+    assert!(gap.intersect(&ray).is_none());
+}
+
+#[test]
+fn test_sdf_lattice_in_csg(){
+    use crate::csg::{Csg, Operation};
+    use crate::cuboid::Cuboid;
+    use crate::{Color, Element};
+
+    // A box cut out of a lattice of balls finds the same balls whether the lattice ends just
+    // outside the box or goes on forever
+    let cut = |count: [u32; 3]| Csg {
+        operation: Operation::Intersection,
+        left: Box::new(Element::Box(Cuboid {
+            min: Point { x: 0.5, y: 0.5, z: 0.5 },
+            max: Point { x: 3.5, y: 3.5, z: 1.5 },
+            transform: None,
+            material: Material::diffuse(Color::black()),
+        })),
+        right: Box::new(Element::Sdf(Sdf {
+            shape: SdfShape::Repeat {
+                shape: Box::new(SdfShape::Sphere { center: Point::zero(), radius: 0.3 }),
+                spacing: Vector3::one_to_all(1.0),
+                count,
+            },
+            material: Material::diffuse(Color::black()),
+        })),
+    };
+    let (bounded, forever) = (cut([5, 5, 3]), cut([0, 0, 0]));
+
+    let mut hits = 0;
+    for i in 0..12 {
+        for j in 0..12 {
+            let ray = Ray {
+                origin: Point { x: 0.3 * i as f64, y: 0.3 * j as f64, z: 10.0 },
+                direction: Vector3 { x: 0.05, y: 0.02, z: -1.0 },
+            };
+            let (a, b) = (bounded.intersect(&ray), forever.intersect(&ray));
+            assert_eq!(a.is_some(), b.is_some());
+            if let (Some(a), Some(b)) = (a, b) {
+                assert!((a - b).abs() < 1e-6);
+                hits += 1;
+            }
+        }
+    }
+    assert!(hits > 10);
+}
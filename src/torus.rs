@@ -0,0 +1,244 @@
+use std::f64::consts::PI;
+use crate::bvh::Aabb;
+use crate::csg::pair_hits;
+use crate::point::Point;
+use crate::transform::Transform;
+use crate::vector::Vector3;
+use crate::{Intersectable, Material, Ray, Texture_thing};
+
+// Ring around the axis (local +y) at major_radius from the center, the tube is minor_radius thick
+#[derive(Debug)]
+pub struct Torus {
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub transform: Transform,
+    pub material: Material,
+}
+
+// Value of the polynomial, coefficients go from the highest power down
+fn evaluate(coefficients: &[f64], t: f64) -> f64 {
+    coefficients.iter().fold(0.0, |sum, &c| sum * t + c)
+}
+
+fn derivative(coefficients: &[f64]) -> Vec<f64> {
+    let degree = coefficients.len() - 1;
+    coefficients[..degree].iter().enumerate().map(|(i, &c)| c * (degree - i) as f64).collect()
+}
+
+// The single root between low and high where the polynomial changes sign, Newton steps
+// that fall outside the bracket are replaced by bisection so it always converges
+fn refine_root(coefficients: &[f64], slope: &[f64], mut low: f64, mut high: f64) -> f64 {
+    let low_sign = evaluate(coefficients, low) < 0.0;
+    let mut t = 0.5 * (low + high);
+
+    for _ in 0..100 {
+        let value = evaluate(coefficients, t);
+        if value == 0.0 {
+            return t;
+        }
+        if (value < 0.0) == low_sign {
+            low = t;
+        } else {
+            high = t;
+        }
+
+        let newton = t - value / evaluate(slope, t);
+        let next = if newton > low && newton < high { newton } else { 0.5 * (low + high) };
+        if (next - t).abs() <= 1e-15 * t.abs().max(1.0) {
+            return next;
+        }
+        t = next;
+    }
+    t
+}
+
+// Real roots in increasing order. Between two neighbouring roots of the derivative the
+// polynomial only goes one way, so each of those stretches holds at most one root and
+// it can be found by bracketing instead of the closed formulas that lose precision.
+// Only roots where the sign changes count, a ray that just touches a surface doesn't go in.
+pub fn solve_polynomial(coefficients: &[f64]) -> Vec<f64> {
+    // Leading zeros make it a lower degree
+    let start = coefficients.iter().position(|&c| c != 0.0).unwrap_or(coefficients.len());
+    let coefficients: Vec<f64> = coefficients[start..].iter().map(|c| c / coefficients[start]).collect();
+
+    match coefficients.len() {
+        0 | 1 => return Vec::new(),
+        2 => return vec![-coefficients[1]],
+        _ => {}
+    }
+
+    // Every root is within this distance of 0 (Cauchy's bound)
+    let bound = 1.0 + coefficients[1..].iter().fold(0.0f64, |max, c| max.max(c.abs()));
+
+    let slope = derivative(&coefficients);
+    let mut edges = vec![-bound];
+    edges.extend(solve_polynomial(&slope).into_iter().filter(|t| t.abs() < bound));
+    edges.push(bound);
+
+    // A root right on an edge is remembered until the next edge shows if the sign changed there
+    let mut roots = Vec::new();
+    let mut last: Option<(f64, f64)> = None;
+    let mut on_edge = None;
+    for &edge in edges.iter() {
+        let value = evaluate(&coefficients, edge);
+        if value == 0.0 {
+            on_edge = Some(edge);
+            continue;
+        }
+        if let Some((before, before_value)) = last {
+            if (before_value < 0.0) != (value < 0.0) {
+                roots.push(on_edge.unwrap_or_else(|| refine_root(&coefficients, &slope, before, edge)));
+            }
+        }
+        last = Some((edge, value));
+        on_edge = None;
+    }
+    roots
+}
+
+impl Torus {
+    fn bounds(&self) -> Aabb {
+        let outer = self.major_radius + self.minor_radius;
+        Aabb {
+            min: Point { x: -outer, y: -self.minor_radius, z: -outer },
+            max: Point { x: outer, y: self.minor_radius, z: outer },
+        }
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        self.transform.bounds_to_world(&self.bounds())
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.major_radius > 0.0 && self.minor_radius > 0.0 && self.major_radius.is_finite() && self.minor_radius.is_finite()
+            && self.transform.is_finite()
+    }
+
+    // Every distance where the ray crosses the surface, sorted
+    pub fn hits(&self, ray: &Ray) -> Vec<f64> {
+        let ray = self.transform.ray_to_local(ray);
+        let (major, minor) = (self.major_radius, self.minor_radius);
+        let d = ray.direction;
+
+        // Start from where the ray reaches the sphere around the torus, the numbers stay small
+        // that way even when the torus is far away
+        let to_center = Point::zero() - ray.origin;
+        let dd = d.dot(&d);
+        let along = to_center.dot(&d) / dd;
+        let outer = major + minor;
+        let closest = (to_center - d * along).norm();
+        if closest > outer * outer {
+            return Vec::new();
+        }
+        let shift = (along - ((outer * outer - closest) / dd).sqrt()).max(0.0);
+        let o = ray.origin + d * shift;
+        let o = o - Point::zero();
+
+        // (|p|² + R² - r²)² = 4 R² (x² + z²) along p = o + t d
+        let p = o.dot(&d);
+        let q = o.dot(&o) + major * major - minor * minor;
+        let four_r2 = 4.0 * major * major;
+        let coefficients = [
+            dd * dd,
+            4.0 * p * dd,
+            4.0 * p * p + 2.0 * dd * q - four_r2 * (d.x * d.x + d.z * d.z),
+            4.0 * p * q - 2.0 * four_r2 * (o.x * d.x + o.z * d.z),
+            q * q - four_r2 * (o.x * o.x + o.z * o.z),
+        ];
+
+        solve_polynomial(&coefficients).into_iter().map(|t| t + shift).collect()
+    }
+
+    // Point on the middle of the tube closest to a local point
+    fn ring_point(&self, p: &Point) -> Point {
+        let flat = Vector3 { x: p.x, y: 0.0, z: p.z };
+        let flat = if flat.length() > 0.0 { flat.normalize() } else { Vector3 { x: 1.0, y: 0.0, z: 0.0 } };
+        Point::zero() + flat * self.major_radius
+    }
+}
+
+impl Intersectable for Torus {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        self.hits(ray).into_iter().find(|&t| t >= 0.0)
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        pair_hits(&self.hits(ray))
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        let p = self.transform.point_to_local(hit_point);
+        self.transform.direction_to_world(&(p - self.ring_point(&p)).normalize())
+    }
+
+    // Around the axis, then around the tube starting from the outside edge
+    fn texture_coords(&self, hit_point: &Point) -> Texture_thing {
+        let p = self.transform.point_to_local(hit_point);
+        let out = (p.x * p.x + p.z * p.z).sqrt() - self.major_radius;
+        Texture_thing {
+            x: (0.5 + p.z.atan2(p.x) / (2.0 * PI)) as f32,
+            y: (0.5 + p.y.atan2(out) / (2.0 * PI)) as f32,
+        }
+    }
+}
+
+#[test]
+fn test_solve_polynomial(){
+    // (t - 1)(t - 2)(t + 3)(t - 10)
+    let roots = solve_polynomial(&[1.0, -10.0, -7.0, 76.0, -60.0]);
+    assert_eq!(roots.len(), 4);
+    for (root, expected) in roots.iter().zip([-3.0, 1.0, 2.0, 10.0].iter()) {
+        assert!((root - expected).abs() < 1e-12);
+    }
+
+    // Two roots very close together, which Ferrari's formula struggles with
+    let (a, b) = (1.0, 1.0 + 1e-6);
+    let roots = solve_polynomial(&[1.0, -(a + b) - 5.0, a * b + 5.0 * (a + b) + 1.0, -(5.0 * a * b + a + b), a * b]);
+    assert!(roots.iter().any(|r| (r - a).abs() < 1e-9) && roots.iter().any(|r| (r - b).abs() < 1e-9));
+
+    // t²(t - 2)(t + 2) only touches 0
+    let roots = solve_polynomial(&[1.0, 0.0, -4.0, 0.0, 0.0]);
+    assert_eq!(roots.len(), 2);
+    assert!((roots[0] + 2.0).abs() < 1e-12 && (roots[1] - 2.0).abs() < 1e-12);
+
+    assert!(solve_polynomial(&[1.0, 0.0, 1.0]).is_empty());
+    assert_eq!(solve_polynomial(&[0.0, 0.0, 2.0, -4.0]), vec![2.0]);
+}
+
+#[test]
+fn test_torus(){
+    use crate::Color;
+
+    let torus = Torus {
+        major_radius: 2.0,
+        minor_radius: 0.5,
+        transform: Transform::with_axis(Vector3 { x: 0.0, y: 0.0, z: -10.0 }, &Vector3 { x: 0.0, y: 0.0, z: 1.0 }),
+        material: Material::diffuse(Color::black()),
+    };
+
+    // Facing the camera like a donut on a plate, the hole lets the middle through
+    let through_hole = Ray { origin: Point::zero(), direction: Vector3 { x: 0.0, y: 0.0, z: -1.0 } };
+    assert_eq!(torus.intersect(&through_hole), None);
+
+    let at_tube = Ray { origin: Point { x: 2.0, y: 0.0, z: 0.0 }, direction: Vector3 { x: 0.0, y: 0.0, z: -1.0 } };
+    assert!((torus.intersect(&at_tube).unwrap() - 9.5).abs() < 1e-9);
+    assert_eq!(torus.hits(&at_tube).len(), 2);
+
+    // Straight across the ring, in and out of the tube on both sides
+    let across = Ray { origin: Point { x: -10.0, y: 0.0, z: -10.0 }, direction: Vector3 { x: 1.0, y: 0.0, z: 0.0 } };
+    let hits = torus.hits(&across);
+    assert_eq!(hits.len(), 4);
+    for (hit, expected) in hits.iter().zip([7.5, 8.5, 11.5, 12.5].iter()) {
+        assert!((hit - expected).abs() < 1e-9);
+    }
+
+    let normal = torus.surface_normal(&Point { x: 2.0, y: 0.0, z: -9.5 });
+    assert!((normal - Vector3 { x: 0.0, y: 0.0, z: 1.0 }).length() < 1e-9);
+
+    // Far away and at a slant it still has to land on the surface
+    let far = Ray { origin: Point { x: 3000.0, y: 1000.0, z: 2000.0 }, direction: (Point { x: 2.3, y: 0.1, z: -10.0 } - Point { x: 3000.0, y: 1000.0, z: 2000.0 }).normalize() };
+    let distance = torus.intersect(&far).unwrap();
+    let local = torus.transform.point_to_local(&(far.origin + far.direction * distance));
+    let tube = ((local.x * local.x + local.z * local.z).sqrt() - 2.0).hypot(local.y);
+    assert!((tube - 0.5).abs() < 1e-9);
+}
@@ -0,0 +1,126 @@
+use crate::bvh::Aabb;
+use crate::point::Point;
+use crate::vector::Vector3;
+use crate::Ray;
+
+// Turns a shape (degrees around x, then y, then z) and moves it, so the shape itself can be
+// worked out in its own space where it is centered and lined up with the axes
+#[derive(Copy, Clone, Debug)]
+pub struct Transform {
+    rotation: [[f64; 3]; 3],
+    pub translation: Vector3,
+}
+
+impl Transform {
+    pub fn new(translation: Vector3, degrees: Vector3) -> Transform {
+        let (sx, cx) = degrees.x.to_radians().sin_cos();
+        let (sy, cy) = degrees.y.to_radians().sin_cos();
+        let (sz, cz) = degrees.z.to_radians().sin_cos();
+
+        // Rz * Ry * Rx
+        Transform {
+            rotation: [
+                [cz * cy, cz * sy * sx - sz * cx, cz * sy * cx + sz * sx],
+                [sz * cy, sz * sy * sx + cz * cx, sz * sy * cx - cz * sx],
+                [-sy, cy * sx, cy * cx],
+            ],
+            translation,
+        }
+    }
+
+    // Local +y ends up pointing along axis, for shapes that are round around it
+    pub fn with_axis(translation: Vector3, axis: &Vector3) -> Transform {
+        let y = axis.normalize();
+        let (x, _) = y.orthonormal_basis();
+        let z = x.cross(&y);
+
+        // The columns are where the local axes end up
+        Transform {
+            rotation: [
+                [x.x, y.x, z.x],
+                [x.y, y.y, z.y],
+                [x.z, y.z, z.z],
+            ],
+            translation,
+        }
+    }
+
+    // False when it was made from a zero axis or infinite numbers
+    pub fn is_finite(&self) -> bool {
+        self.translation.is_finite() && self.rotation.iter().flatten().all(|v| v.is_finite())
+    }
+
+    pub fn direction_to_world(&self, v: &Vector3) -> Vector3 {
+        let m = &self.rotation;
+        Vector3 {
+            x: m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            y: m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            z: m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        }
+    }
+
+    // A rotation is undone by its transpose
+    pub fn direction_to_local(&self, v: &Vector3) -> Vector3 {
+        let m = &self.rotation;
+        Vector3 {
+            x: m[0][0] * v.x + m[1][0] * v.y + m[2][0] * v.z,
+            y: m[0][1] * v.x + m[1][1] * v.y + m[2][1] * v.z,
+            z: m[0][2] * v.x + m[1][2] * v.y + m[2][2] * v.z,
+        }
+    }
+
+    pub fn point_to_world(&self, p: &Point) -> Point {
+        Point::zero() + self.direction_to_world(&(*p - Point::zero())) + self.translation
+    }
+
+    pub fn point_to_local(&self, p: &Point) -> Point {
+        Point::zero() + self.direction_to_local(&(*p - Point::zero() - self.translation))
+    }
+
+    // World box around a box in local space
+    pub fn bounds_to_world(&self, local: &Aabb) -> Aabb {
+        let mut bounds = Aabb::empty();
+        for i in 0..8 {
+            let corner = Point {
+                x: if i & 1 == 0 { local.min.x } else { local.max.x },
+                y: if i & 2 == 0 { local.min.y } else { local.max.y },
+                z: if i & 4 == 0 { local.min.z } else { local.max.z },
+            };
+            let corner = self.point_to_world(&corner);
+            bounds = bounds.union(&Aabb { min: corner, max: corner });
+        }
+        bounds
+    }
+
+    // Distances along the ray stay the same, nothing gets scaled
+    pub fn ray_to_local(&self, ray: &Ray) -> Ray {
+        Ray {
+            origin: self.point_to_local(&ray.origin),
+            direction: self.direction_to_local(&ray.direction),
+        }
+    }
+}
+
+#[test]
+fn test_transform(){
+    let transform = Transform::new(Vector3 { x: 1.0, y: 2.0, z: 3.0 }, Vector3 { x: 0.0, y: 0.0, z: 90.0 });
+
+    let world = transform.point_to_world(&Point { x: 1.0, y: 0.0, z: 0.0 });
+    assert!((world - Point { x: 1.0, y: 3.0, z: 3.0 }).length() < 1e-9);
+
+    let local = transform.point_to_local(&world);
+    assert!((local - Point { x: 1.0, y: 0.0, z: 0.0 }).length() < 1e-9);
+
+    let tilted = Transform::new(Vector3::zero(), Vector3 { x: 30.0, y: -45.0, z: 10.0 });
+    let v = Vector3 { x: 0.3, y: -2.0, z: 0.7 };
+    let there = tilted.direction_to_world(&v);
+    assert!((there.length() - v.length()).abs() < 1e-9);
+    assert!((tilted.direction_to_local(&there) - v).length() < 1e-9);
+
+    let axis = Vector3 { x: 1.0, y: 1.0, z: 0.0 };
+    let along = Transform::with_axis(Vector3::zero(), &axis);
+    let up = along.direction_to_world(&Vector3 { x: 0.0, y: 1.0, z: 0.0 });
+    assert!((up - axis.normalize()).length() < 1e-9);
+    let (x, z) = (along.direction_to_world(&Vector3 { x: 1.0, y: 0.0, z: 0.0 }), along.direction_to_world(&Vector3 { x: 0.0, y: 0.0, z: 1.0 }));
+    assert!((x.cross(&up) - z).length() < 1e-9);
+}
@@ -1,4 +1,4 @@
-use std::ops::{Add, Sub, Mul, Neg};
+use std::ops::{Add, Sub, Mul};
 
 #[derive(Copy, Clone, Debug)]
 pub struct Vector3{
@@ -16,6 +16,10 @@ impl Vector3{
         Vector3 { x: v, y: v, z: v }
     }
 
+    pub fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
+    }
+
     pub fn norm(&self) -> f64 {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
@@ -44,6 +48,17 @@ impl Vector3{
             z: self.x*other.y - self.y*other.x,
         }
     }
+
+    // Two unit vectors perpendicular to this one and to each other, self should be normalized
+    pub fn orthonormal_basis(&self) -> (Vector3, Vector3) {
+        let helper = if self.x.abs() > 0.9 {
+            Vector3 { x: 0.0, y: 1.0, z: 0.0 }
+        } else {
+            Vector3 { x: 1.0, y: 0.0, z: 0.0 }
+        };
+        let tangent = self.cross(&helper).normalize();
+        (tangent, self.cross(&tangent))
+    }
 }
 
 impl Add for Vector3 {